@@ -0,0 +1,4 @@
+// every error code the api can return, with its status, numeric id and default message
+pub async fn list() -> axum::response::Response {
+    crate::response::success(crate::response::error::registry())
+}
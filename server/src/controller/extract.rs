@@ -0,0 +1,541 @@
+pub const DEFAULT_LOCALE: &str = "en";
+
+// Preferred language of the client, the primary subtag (`fr` for `fr-CH`) of the highest
+// weighted `Accept-Language` entry, `en` when the header is missing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Locale(pub String);
+
+impl Locale {
+    pub fn from_headers(headers: &axum::http::HeaderMap) -> Self {
+        let header = headers
+            .get(axum::http::header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+
+        let mut preferred: Option<(&str, f32)> = None;
+        for entry in header.split(',') {
+            let mut parts = entry.split(';');
+            let tag = parts.next().unwrap_or_default().trim();
+            let weight = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if tag.is_empty() || tag == "*" || weight <= 0.0 {
+                continue;
+            }
+            if preferred.map_or(true, |(_, best)| weight > best) {
+                preferred = Some((tag, weight));
+            }
+        }
+
+        let language = preferred
+            .and_then(|(tag, _)| tag.split('-').next())
+            .unwrap_or(DEFAULT_LOCALE);
+        Locale(language.to_ascii_lowercase())
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequestParts<S> for Locale {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(Locale::from_headers(&parts.headers))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid json body: {0}")]
+pub struct JsonError(pub axum::extract::rejection::JsonRejection);
+
+impl crate::response::error::ResponseError for JsonError {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        match self.0.status() {
+            axum::http::StatusCode::PAYLOAD_TOO_LARGE => {
+                crate::response::error::ErrorCode::PayloadTooLarge
+            }
+            // missing or non json `Content-Type`
+            axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE => {
+                crate::response::error::ErrorCode::UnsupportedMediaType
+            }
+            _ => crate::response::error::ErrorCode::BadRequest,
+        }
+    }
+
+    fn user_message(&self) -> String {
+        "Request body is not valid".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.0.body_text())
+    }
+}
+
+// Drop in replacement of `axum::Json` whose rejections (malformed json, missing fields, ...)
+// are rendered through our error envelope instead of axum's plain text body
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequest<S> for Json<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = crate::response::error::ApiError;
+
+    async fn from_request(
+        request: axum::extract::Request,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+        match axum::Json::<T>::from_request(request, state).await {
+            Ok(axum::Json(value)) => Ok(Json(value)),
+            Err(rejection) => Err(crate::response::error::ApiError::new(
+                &JsonError(rejection),
+                "request.json",
+                request_id.as_str(),
+            )),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid query string: {0}")]
+pub struct QueryError(pub serde_path_to_error::Error<serde_urlencoded::de::Error>);
+
+impl crate::response::error::ResponseError for QueryError {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::BadRequest
+    }
+
+    fn user_message(&self) -> String {
+        "Query parameters are not valid".to_string()
+    }
+
+    // names the offending parameter, e.g. `page: invalid digit found in string`
+    fn technical_description(&self) -> Option<String> {
+        Some(self.0.to_string())
+    }
+}
+
+// `axum::extract::Query` with rejections rendered through our error envelope, used by the list
+// endpoints for `PageQuery`, `CursorQuery` and their filters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Query<T>(pub T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequestParts<S> for Query<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = crate::response::error::ApiError;
+
+    // deserialized like `axum::extract::Query`, but keeping the path of the failing parameter
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let query = parts.uri.query().unwrap_or_default();
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        match serde_path_to_error::deserialize(deserializer) {
+            Ok(value) => Ok(Query(value)),
+            Err(err) => Err(crate::response::error::ApiError::new(
+                &QueryError(err),
+                "request.query",
+                crate::middleware::request_id::RequestId::from_parts(parts).as_str(),
+            )),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InvalidId {
+    #[error("id must be a UUID, got `{0}`")]
+    Malformed(String),
+    #[error("invalid path: {0}")]
+    Path(axum::extract::rejection::PathRejection),
+}
+
+impl crate::response::error::ResponseError for InvalidId {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::BadRequest
+    }
+
+    fn user_message(&self) -> String {
+        "The id in the path is not valid".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+// Single `:id` path segment which has to be a UUID, malformed ids are rejected with BadRequest
+// before the handler (and the service behind it) sees them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValidatedId(pub uuid::Uuid);
+
+#[axum::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequestParts<S> for ValidatedId {
+    type Rejection = crate::response::error::ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let result = match axum::extract::Path::<String>::from_request_parts(parts, state).await {
+            Ok(axum::extract::Path(id)) => uuid::Uuid::parse_str(id.as_str())
+                .map(ValidatedId)
+                .map_err(|_| InvalidId::Malformed(id)),
+            Err(rejection) => Err(InvalidId::Path(rejection)),
+        };
+        result.map_err(|err| {
+            crate::response::error::ApiError::new(
+                &err,
+                "request.path_id",
+                crate::middleware::request_id::RequestId::from_parts(parts).as_str(),
+            )
+        })
+    }
+}
+
+// User of an authenticated request, built from the claims the `auth` middleware verified.
+// Rejects with UnAuthorized when the route is not behind that middleware or had no token.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: String,
+    pub roles: Vec<String>,
+}
+
+impl AuthUser {
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequestParts<S> for AuthUser {
+    type Rejection = crate::response::error::ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<crate::middleware::auth::Claims>() {
+            Some(claims) => Ok(AuthUser {
+                user_id: claims.sub.clone(),
+                roles: claims.roles.clone(),
+            }),
+            None => Err(crate::response::error::ApiError::new(
+                &crate::middleware::auth::AuthError::MissingToken,
+                "request.auth_user",
+                crate::middleware::request_id::RequestId::from_parts(parts).as_str(),
+            )),
+        }
+    }
+}
+
+// Role a `RequireRole` guard checks for, one marker type per role
+pub trait Role {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+impl Role for Admin {
+    const NAME: &'static str = "admin";
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("user `{user_id}` lacks the required role `{role}`")]
+pub struct MissingRole {
+    pub user_id: String,
+    pub role: &'static str,
+}
+
+impl crate::response::error::ResponseError for MissingRole {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::Forbidden
+    }
+
+    fn user_message(&self) -> String {
+        "You are not allowed to perform this action".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(format!("requires the `{}` role", self.role))
+    }
+}
+
+// Guard for handlers restricted to a role, e.g. `RequireRole<Admin>`. Unauthenticated requests
+// get UnAuthorized, authenticated users without the role get Forbidden.
+#[derive(Debug, Clone)]
+pub struct RequireRole<R: Role> {
+    pub user: AuthUser,
+    role: std::marker::PhantomData<R>,
+}
+
+#[axum::async_trait]
+impl<R: Role, S: Send + Sync> axum::extract::FromRequestParts<S> for RequireRole<R> {
+    type Rejection = crate::response::error::ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if !user.has_role(R::NAME) {
+            return Err(crate::response::error::ApiError::new(
+                &MissingRole {
+                    user_id: user.user_id,
+                    role: R::NAME,
+                },
+                "request.require_role",
+                crate::middleware::request_id::RequestId::from_parts(parts).as_str(),
+            ));
+        }
+        Ok(RequireRole {
+            user,
+            role: std::marker::PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[derive(serde::Deserialize)]
+    struct ListQuery {
+        #[serde(default = "default_page")]
+        page: u32,
+        status: String,
+    }
+
+    fn default_page() -> u32 {
+        1
+    }
+
+    async fn list(super::Query(query): super::Query<ListQuery>) -> axum::response::Response {
+        crate::response::success(format!("{}:{}", query.page, query.status))
+    }
+
+    async fn get(uri: &str) -> axum::response::Response {
+        let router = axum::Router::new().route("/items", axum::routing::get(list));
+        let request = axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn valid_query_is_deserialized() {
+        let response = get("/items?page=2&status=active").await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "2:active");
+    }
+
+    #[tokio::test]
+    async fn non_numeric_page_is_bad_request() {
+        let response = get("/items?page=two&status=active").await;
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["code"], "BAD_REQUEST");
+        assert_eq!(body["error"]["operation"], "request.query");
+        let description = body["error"]["description"].as_str().unwrap();
+        assert!(description.contains("page"), "{}", description);
+    }
+
+    #[tokio::test]
+    async fn missing_filter_is_bad_request() {
+        let response = get("/items?page=2").await;
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["code"], "BAD_REQUEST");
+        let description = body["error"]["description"].as_str().unwrap();
+        assert!(description.contains("status"), "{}", description);
+    }
+
+    #[derive(serde::Deserialize)]
+    struct CreateItem {
+        name: String,
+    }
+
+    async fn create(super::Json(item): super::Json<CreateItem>) -> axum::response::Response {
+        crate::response::success(item.name)
+    }
+
+    async fn post_json(body: &'static str) -> axum::response::Response {
+        let router = axum::Router::new().route("/items", axum::routing::post(create));
+        let request = axum::http::Request::post("/items")
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        crate::router::tests::send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn truncated_json_gets_the_bad_request_envelope() {
+        let response = post_json(r#"{"name":"#).await;
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "BAD_REQUEST");
+        assert_eq!(body["error"]["message"], "Request body is not valid");
+        assert_eq!(body["error"]["operation"], "request.json");
+        assert!(body["error"]["trace_id"].is_string());
+        let description = body["error"]["description"].as_str().unwrap();
+        assert!(description.contains("EOF"), "{}", description);
+    }
+
+    #[tokio::test]
+    async fn valid_json_is_deserialized() {
+        let response = post_json(r#"{"name":"a"}"#).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "a");
+    }
+
+    async fn me(user: super::AuthUser) -> axum::response::Response {
+        crate::response::success(user.user_id)
+    }
+
+    fn claims(roles: &[&str]) -> crate::middleware::auth::Claims {
+        crate::middleware::auth::Claims {
+            sub: "user-1".to_string(),
+            exp: u64::MAX,
+            roles: roles.iter().map(|role| role.to_string()).collect(),
+            tenant: None,
+        }
+    }
+
+    async fn get_with(
+        router: axum::Router,
+        authorization: Option<&str>,
+    ) -> axum::response::Response {
+        let mut request = axum::http::Request::get("/me");
+        if let Some(authorization) = authorization {
+            request = request.header(axum::http::header::AUTHORIZATION, authorization);
+        }
+        let request = request.body(axum::body::Body::empty()).unwrap();
+        crate::router::tests::send(router, request).await
+    }
+
+    async fn assert_unauthorized(response: axum::response::Response, operation: &str) {
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+        assert_eq!(body["error"]["operation"], operation);
+    }
+
+    #[tokio::test]
+    async fn auth_user_without_claims_is_unauthorized() {
+        let router = axum::Router::new().route("/me", axum::routing::get(me));
+        assert_unauthorized(get_with(router, None).await, "request.auth_user").await;
+    }
+
+    #[tokio::test]
+    async fn auth_user_with_a_bad_token_is_unauthorized() {
+        let auth = crate::middleware::auth::Auth::new(&crate::settings::AuthSettings {
+            jwt_secret: "test-secret".to_string(),
+        });
+        let router = axum::Router::new()
+            .route("/me", axum::routing::get(me))
+            .route_layer(axum::middleware::from_fn_with_state(
+                auth,
+                crate::middleware::auth::auth,
+            ));
+        let response = get_with(router, Some("Bearer not-a-jwt")).await;
+        assert_unauthorized(response, "request.auth").await;
+    }
+
+    #[tokio::test]
+    async fn auth_user_is_built_from_the_claims() {
+        let router = axum::Router::new()
+            .route("/me", axum::routing::get(me))
+            .layer(axum::Extension(claims(&[])));
+        let response = get_with(router, None).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "user-1");
+    }
+
+    async fn admin_only(admin: super::RequireRole<super::Admin>) -> axum::response::Response {
+        crate::response::success(admin.user.user_id)
+    }
+
+    async fn get_admin(roles: &[&str]) -> axum::response::Response {
+        let router = axum::Router::new()
+            .route("/me", axum::routing::get(admin_only))
+            .layer(axum::Extension(claims(roles)));
+        get_with(router, None).await
+    }
+
+    #[tokio::test]
+    async fn require_role_rejects_a_user_without_the_role() {
+        let response = get_admin(&["viewer"]).await;
+        assert_eq!(response.status(), axum::http::StatusCode::FORBIDDEN);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["code"], "FORBIDDEN");
+        assert_eq!(body["error"]["operation"], "request.require_role");
+        assert_eq!(
+            body["error"]["message"],
+            "You are not allowed to perform this action"
+        );
+    }
+
+    #[tokio::test]
+    async fn require_role_lets_an_admin_through() {
+        let response = get_admin(&["viewer", "admin"]).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "user-1");
+    }
+
+    async fn get_template(uri: &str) -> axum::response::Response {
+        let router = axum::Router::new().route(
+            "/templates/:id",
+            axum::routing::get(|super::ValidatedId(id): super::ValidatedId| async move {
+                crate::response::success(id.to_string())
+            }),
+        );
+        let request = axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn malformed_id_is_bad_request() {
+        let response = get_template("/templates/not-a-uuid").await;
+        assert_eq!(response.status(), axum::http::StatusCode::BAD_REQUEST);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["code"], "BAD_REQUEST");
+        assert_eq!(body["error"]["operation"], "request.path_id");
+        assert_eq!(body["error"]["message"], "The id in the path is not valid");
+        assert_eq!(
+            body["error"]["description"],
+            "id must be a UUID, got `not-a-uuid`"
+        );
+    }
+
+    #[tokio::test]
+    async fn valid_id_reaches_the_handler() {
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let response = get_template(format!("/templates/{}", id).as_str()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], id);
+    }
+}
@@ -0,0 +1,171 @@
+#[derive(thiserror::Error, Debug)]
+#[error("no route for `{path}`")]
+pub struct RouteNotFound {
+    pub path: String,
+}
+
+impl crate::response::error::ResponseError for RouteNotFound {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::NotFound
+    }
+
+    fn user_message(&self) -> String {
+        "Route not found".to_string()
+    }
+
+    fn localized_message(&self, locale: &str) -> String {
+        match locale {
+            "de" => "Route nicht gefunden".to_string(),
+            "fr" => "Route introuvable".to_string(),
+            _ => self.user_message(),
+        }
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.path.clone())
+    }
+}
+
+// Unmatched routes, answered with the error envelope instead of axum's empty 404, in the
+// language of the client's `Accept-Language`
+pub async fn not_found(
+    locale: crate::controller::extract::Locale,
+    uri: axum::http::Uri,
+) -> axum::response::Response {
+    crate::response::error::localized_response(
+        &RouteNotFound {
+            path: uri.path().to_string(),
+        },
+        "route.not_found",
+        crate::response::error::trace_id().as_str(),
+        &locale,
+    )
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("method `{method}` is not allowed for `{path}`")]
+pub struct MethodNotAllowed {
+    pub method: String,
+    pub path: String,
+}
+
+impl crate::response::error::ResponseError for MethodNotAllowed {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::MethodNotAllowed
+    }
+
+    fn user_message(&self) -> String {
+        "Method not allowed".to_string()
+    }
+
+    fn localized_message(&self, locale: &str) -> String {
+        match locale {
+            "de" => "Methode nicht erlaubt".to_string(),
+            "fr" => "Méthode non autorisée".to_string(),
+            _ => self.user_message(),
+        }
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+// Known path hit with a method it has no handler for, axum adds the `Allow` header listing the
+// methods the path does accept
+pub async fn method_not_allowed(
+    locale: crate::controller::extract::Locale,
+    method: axum::http::Method,
+    uri: axum::http::Uri,
+) -> axum::response::Response {
+    crate::response::error::localized_response(
+        &MethodNotAllowed {
+            method: method.to_string(),
+            path: uri.path().to_string(),
+        },
+        "route.method_not_allowed",
+        crate::response::error::trace_id().as_str(),
+        &locale,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::tests::{body_json, send_default};
+
+    #[tokio::test]
+    async fn unknown_path_gets_not_found_envelope() {
+        let request = axum::http::Request::get("/no/such/route")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = send_default(request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        let body = body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "NOT_FOUND");
+        assert_eq!(body["error"]["message"], "Route not found");
+        assert_eq!(body["error"]["description"], "/no/such/route");
+    }
+
+    #[tokio::test]
+    async fn disallowed_method_gets_405_envelope_and_allow_header() {
+        let request = axum::http::Request::post("/v1/api/health/")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = send_default(request).await;
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::METHOD_NOT_ALLOWED
+        );
+        let allow = response.headers()[axum::http::header::ALLOW]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(
+            allow.split(',').any(|method| method.trim() == "GET"),
+            "{}",
+            allow
+        );
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["code"], "METHOD_NOT_ALLOWED");
+        assert_eq!(body["error"]["code_id"], 1012);
+    }
+
+    async fn not_found_message(accept_language: Option<&str>) -> serde_json::Value {
+        let mut request = axum::http::Request::get("/no/such/route");
+        if let Some(accept_language) = accept_language {
+            request = request.header(axum::http::header::ACCEPT_LANGUAGE, accept_language);
+        }
+        let response = send_default(request.body(axum::body::Body::empty()).unwrap()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        body_json(response).await["error"]["message"].clone()
+    }
+
+    #[tokio::test]
+    async fn accept_language_picks_the_translation() {
+        assert_eq!(
+            not_found_message(Some("de-CH, fr;q=0.8")).await,
+            "Route nicht gefunden"
+        );
+        assert_eq!(
+            not_found_message(Some("en;q=0.5, fr")).await,
+            "Route introuvable"
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_language_falls_back_to_english() {
+        assert_eq!(not_found_message(Some("ja")).await, "Route not found");
+        assert_eq!(not_found_message(None).await, "Route not found");
+    }
+
+    #[tokio::test]
+    async fn method_not_allowed_is_translated() {
+        let request = axum::http::Request::post("/v1/api/health/")
+            .header(axum::http::header::ACCEPT_LANGUAGE, "fr-FR")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let body = body_json(send_default(request).await).await;
+        assert_eq!(body["error"]["message"], "Méthode non autorisée");
+    }
+}
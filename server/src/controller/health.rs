@@ -0,0 +1,96 @@
+// Flipped on by the listener once it is accepting connections, stands in for real checks of
+// the downstream dependencies (db pool etc.) until the service has some
+static READY: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_ready(ready: bool) {
+    READY.store(ready, std::sync::atomic::Ordering::SeqCst);
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct HealthStatus {
+    pub status: &'static str,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReadinessError {
+    #[error("dependency `{0}` is not ready")]
+    NotReady(&'static str),
+}
+
+impl crate::response::error::ResponseError for ReadinessError {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::ServiceUnavailable
+    }
+
+    fn user_message(&self) -> String {
+        "Service is not ready to accept traffic".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+fn check_dependencies(listening: bool) -> Result<(), ReadinessError> {
+    if listening {
+        Ok(())
+    } else {
+        Err(ReadinessError::NotReady("http-listener"))
+    }
+}
+
+// liveness: the process is up and serving requests
+pub async fn health() -> axum::response::Response {
+    crate::response::success(HealthStatus { status: "ok" })
+}
+
+// readiness: the service and its dependencies can take traffic
+pub async fn ready() -> Result<axum::response::Response, crate::response::error::AppError> {
+    readiness(READY.load(std::sync::atomic::Ordering::SeqCst))
+}
+
+// `ready` for a given listener state, apart from the global the listener flips
+fn readiness(
+    listening: bool,
+) -> Result<axum::response::Response, crate::response::error::AppError> {
+    check_dependencies(listening)
+        .map_err(|err| crate::response::error::AppError::new(err, "health.ready"))?;
+    Ok(crate::response::success(HealthStatus { status: "ready" }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    #[tokio::test]
+    async fn ready_once_listening() {
+        let response = super::readiness(true).into_response();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"]["status"], "ready");
+    }
+
+    #[tokio::test]
+    async fn not_ready_is_service_unavailable() {
+        let response = super::readiness(false).into_response();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "SERVICE_UNAVAILABLE");
+        assert_eq!(body["error"]["operation"], "health.ready");
+    }
+
+    #[tokio::test]
+    async fn health_is_always_ok() {
+        let request = axum::http::Request::get("/v1/api/health/")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send_default(request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"]["status"], "ok");
+    }
+}
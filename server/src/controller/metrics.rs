@@ -0,0 +1,78 @@
+pub const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// request and error counters in the Prometheus text format, outside of the json envelope so
+// scrapers can read it as is
+pub async fn metrics() -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    (
+        [(axum::http::header::CONTENT_TYPE, PROMETHEUS_CONTENT_TYPE)],
+        crate::metrics::render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    async fn get(uri: &str) -> axum::response::Response {
+        let settings = crate::router::tests::settings();
+        let request = axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(crate::router::routes(&settings).await, request).await
+    }
+
+    async fn scrape() -> String {
+        let response = get("/metrics").await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            super::PROMETHEUS_CONTENT_TYPE
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    // value of the sample named `series`, zero while it was never incremented
+    fn sample(metrics: &str, series: &str) -> u64 {
+        metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .map_or(0, |value| value.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn not_found_is_counted_and_rendered() {
+        let requests = "http_requests_total{path=\"unmatched\",status=\"404\"}";
+        let errors = "http_errors_total{code=\"NotFound\"}";
+        let before = scrape().await;
+
+        let response = get("/v1/api/missing").await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+
+        let after = scrape().await;
+        assert!(
+            after.contains("# TYPE http_requests_total counter"),
+            "{}",
+            after
+        );
+        assert!(
+            after.contains("# TYPE http_errors_total counter"),
+            "{}",
+            after
+        );
+        // other tests share the process wide counters, they can only add to them
+        assert!(
+            sample(&after, requests) > sample(&before, requests),
+            "{}",
+            after
+        );
+        assert!(
+            sample(&after, errors) > sample(&before, errors),
+            "{}",
+            after
+        );
+    }
+}
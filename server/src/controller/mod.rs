@@ -0,0 +1,9 @@
+pub mod error_codes;
+pub mod extract;
+pub mod fallback;
+pub mod health;
+pub mod metrics;
+pub mod openapi;
+pub mod schema;
+pub mod types;
+pub mod validate;
@@ -0,0 +1,262 @@
+// Hand written OpenAPI 3.0 description of the api, served by `GET /openapi.json`. Add the path
+// and its schemas here together with every new route.
+pub fn spec() -> serde_json::Value {
+    let error_codes: Vec<serde_json::Value> = crate::response::error::ErrorCode::ALL
+        .iter()
+        .map(|code| serde_json::to_value(code).unwrap_or_default())
+        .collect();
+
+    serde_json::json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "axum-template",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": {
+            "/v1/api/health/": {
+                "get": {
+                    "summary": "Liveness, the process is up and serving requests",
+                    "responses": {
+                        "200": success_response("HealthStatus"),
+                    },
+                },
+            },
+            "/v1/api/ready/": {
+                "get": {
+                    "summary": "Readiness, the service and its dependencies can take traffic",
+                    "responses": {
+                        "200": success_response("HealthStatus"),
+                        "503": error_response(),
+                    },
+                },
+            },
+            "/v1/api/error-codes/": {
+                "get": {
+                    "summary": "Every error code the api can return",
+                    "responses": {
+                        "200": {
+                            "description": "OK",
+                            "content": {
+                                "application/json": {
+                                    "schema": envelope(serde_json::json!({
+                                        "type": "array",
+                                        "items": { "$ref": "#/components/schemas/ErrorCodeInfo" },
+                                    })),
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "/docs": {
+                "get": {
+                    "summary": "Interactive documentation of this spec",
+                    "responses": {
+                        "200": {
+                            "description": "OK",
+                            "content": { "text/html": { "schema": { "type": "string" } } },
+                        },
+                    },
+                },
+            },
+            "/metrics": {
+                "get": {
+                    "summary": "Request and error counters in the Prometheus text format",
+                    "responses": {
+                        "200": {
+                            "description": "OK",
+                            "content": {
+                                crate::controller::metrics::PROMETHEUS_CONTENT_TYPE: {
+                                    "schema": { "type": "string" },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "ErrorCode": {
+                    "type": "string",
+                    "enum": error_codes,
+                },
+                "FieldError": {
+                    "type": "object",
+                    "required": ["field", "message"],
+                    "properties": {
+                        "field": { "type": "string" },
+                        "message": { "type": "string" },
+                    },
+                },
+                "ErrorHints": {
+                    "type": "object",
+                    "required": ["retryable"],
+                    "properties": {
+                        "retryable": { "type": "boolean" },
+                        "documentation_url": { "type": "string" },
+                    },
+                },
+                "ApiError": {
+                    "type": "object",
+                    "required": [
+                        "code", "code_id", "message", "operation", "trace_id", "timestamp", "status",
+                    ],
+                    "properties": {
+                        "code": { "$ref": "#/components/schemas/ErrorCode" },
+                        "code_id": { "type": "integer" },
+                        "message": { "type": "string" },
+                        "description": { "type": "string" },
+                        "details": { "type": "string" },
+                        "details_chain": { "type": "array", "items": { "type": "string" } },
+                        "field_errors": {
+                            "type": "array",
+                            "items": { "$ref": "#/components/schemas/FieldError" },
+                        },
+                        "hints": { "$ref": "#/components/schemas/ErrorHints" },
+                        "variant": { "type": "string" },
+                        "operation": { "type": "string" },
+                        "trace_id": { "type": "string" },
+                        "user_id": { "type": "string" },
+                        "tenant_id": { "type": "string" },
+                        // rfc3339 string or epoch millis, see `response.timestamp-format`
+                        "timestamp": {},
+                        "status": { "type": "integer" },
+                    },
+                },
+                "ApiErrorResponse": {
+                    "type": "object",
+                    "required": ["success", "error"],
+                    "properties": {
+                        "success": { "type": "boolean", "enum": [false] },
+                        "error": { "$ref": "#/components/schemas/ApiError" },
+                    },
+                },
+                "ErrorCodeInfo": {
+                    "type": "object",
+                    "required": ["code", "code_id", "status", "message"],
+                    "properties": {
+                        "code": { "$ref": "#/components/schemas/ErrorCode" },
+                        "code_id": { "type": "integer" },
+                        "status": { "type": "integer" },
+                        "message": { "type": "string" },
+                    },
+                },
+                "HealthStatus": {
+                    "type": "object",
+                    "required": ["status"],
+                    "properties": {
+                        "status": { "type": "string" },
+                    },
+                },
+            },
+        },
+    })
+}
+
+// `ApiSuccess` with `data` of the given schema
+fn envelope(data: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "type": "object",
+        "required": ["success", "data"],
+        "properties": {
+            "success": { "type": "boolean", "enum": [true] },
+            "data": data,
+            "meta": {},
+            "trace_id": { "type": "string" },
+            "warnings": { "type": "array", "items": { "type": "string" } },
+        },
+    })
+}
+
+fn success_response(schema: &str) -> serde_json::Value {
+    serde_json::json!({
+        "description": "OK",
+        "content": {
+            "application/json": {
+                "schema": envelope(serde_json::json!({
+                    "$ref": format!("#/components/schemas/{}", schema),
+                })),
+            },
+        },
+    })
+}
+
+fn error_response() -> serde_json::Value {
+    serde_json::json!({
+        "description": "Error",
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/ApiErrorResponse" },
+            },
+        },
+    })
+}
+
+// the spec itself, outside of the json envelope so tools can load it as is
+pub async fn openapi() -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    axum::Json(spec()).into_response()
+}
+
+// Redoc shell rendering `/openapi.json`, the page itself is static. The bundle is pinned to an
+// exact version, bumping it is a deliberate change.
+const DOCS_HTML: &str = r#"<!DOCTYPE html>
+<html>
+  <head>
+    <title>axum-template api</title>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+  </head>
+  <body>
+    <redoc spec-url="/openapi.json"></redoc>
+    <script
+      src="https://cdn.jsdelivr.net/npm/redoc@2.1.5/bundles/redoc.standalone.js"
+      crossorigin="anonymous"
+    ></script>
+  </body>
+</html>
+"#;
+
+pub async fn docs() -> axum::response::Html<&'static str> {
+    axum::response::Html(DOCS_HTML)
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn docs_page_is_html_with_a_pinned_bundle() {
+        let request = axum::http::Request::get("/docs")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send_default(request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert!(response.headers()[axum::http::header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(r#"<redoc spec-url="/openapi.json">"#));
+        assert!(body.contains("redoc@2.1.5"));
+        assert!(!body.contains("latest"));
+    }
+
+    #[tokio::test]
+    async fn document_lists_the_served_paths() {
+        let request = axum::http::Request::get("/openapi.json")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send_default(request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let document = crate::router::tests::body_json(response).await;
+        assert!(document["openapi"].is_string());
+        for path in ["/v1/api/health/", "/v1/api/ready/", "/metrics"] {
+            assert!(document["paths"][path]["get"].is_object(), "{}", path);
+        }
+    }
+}
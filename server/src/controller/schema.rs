@@ -0,0 +1,101 @@
+use crate::response::error::FieldError;
+
+// JSON Schema checked against the raw body before it is turned into the typed request, so a
+// single response can list every rule the body breaks. Compile once and keep it around, e.g.
+// in a `OnceLock`, compiling is far more expensive than validating.
+pub struct Schema {
+    compiled: jsonschema::JSONSchema,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("invalid json schema: {0}")]
+pub struct SchemaError(String);
+
+impl Schema {
+    pub fn compile(schema: &serde_json::Value) -> Result<Self, SchemaError> {
+        jsonschema::JSONSchema::compile(schema)
+            .map(|compiled| Schema { compiled })
+            .map_err(|err| SchemaError(err.to_string()))
+    }
+
+    pub fn validate(
+        &self,
+        body: &serde_json::Value,
+    ) -> Result<(), crate::controller::validate::ValidationError> {
+        let errors = match self.compiled.validate(body) {
+            Ok(()) => return Ok(()),
+            Err(errors) => errors,
+        };
+        Err(crate::controller::validate::ValidationError(
+            errors
+                .map(|err| FieldError {
+                    field: field_name(&err),
+                    message: err.to_string(),
+                })
+                .collect(),
+        ))
+    }
+}
+
+// Path of the failing value in the same `items[0].name` shape `Validator` uses. A missing
+// required property is reported at its parent, the property name is appended so the client
+// still sees which field is missing.
+fn field_name(err: &jsonschema::ValidationError) -> String {
+    let mut segments = err.instance_path.clone().into_vec();
+    if let jsonschema::error::ValidationErrorKind::Required {
+        property: serde_json::Value::String(property),
+    } = &err.kind
+    {
+        segments.push(property.clone());
+    }
+
+    let mut field = String::new();
+    for segment in segments {
+        if segment.parse::<usize>().is_ok() {
+            field.push_str(format!("[{}]", segment).as_str());
+        } else {
+            if !field.is_empty() {
+                field.push('.');
+            }
+            field.push_str(segment.as_str());
+        }
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    fn schema() -> super::Schema {
+        super::Schema::compile(&serde_json::json!({
+            "type": "object",
+            "required": ["title", "items"],
+            "properties": {
+                "title": { "type": "string" },
+                "items": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "name": { "type": "string" } }
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn every_violation_is_reported_with_its_field() {
+        let err = schema()
+            .validate(&serde_json::json!({ "items": [{ "name": 1 }] }))
+            .unwrap_err();
+        let mut fields: Vec<&str> = err.0.iter().map(|error| error.field.as_str()).collect();
+        fields.sort_unstable();
+        assert_eq!(fields, vec!["items[0].name", "title"]);
+    }
+
+    #[test]
+    fn valid_body_passes() {
+        let body = serde_json::json!({ "title": "t", "items": [{ "name": "a" }] });
+        assert!(schema().validate(&body).is_ok());
+    }
+}
@@ -0,0 +1,444 @@
+pub const DEFAULT_PAGE: u32 = 1;
+pub const DEFAULT_PER_PAGE: u32 = 20;
+pub const DEFAULT_MAX_PER_PAGE: u32 = 100;
+
+static MAX_PER_PAGE: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+
+pub fn set_max_per_page(max: u32) {
+    let _ = MAX_PER_PAGE.set(max);
+}
+
+// largest `per_page` (and cursor `limit`) accepted, bigger ones are rejected and not clamped
+pub fn max_per_page() -> u32 {
+    MAX_PER_PAGE.get().copied().unwrap_or(DEFAULT_MAX_PER_PAGE)
+}
+
+// `?page=..&per_page=..` of the list endpoints
+#[derive(Debug, serde::Deserialize)]
+pub struct PageQuery {
+    #[serde(default = "default_page")]
+    pub page: u32,
+    #[serde(default = "default_per_page")]
+    pub per_page: u32,
+}
+
+fn default_page() -> u32 {
+    DEFAULT_PAGE
+}
+
+fn default_per_page() -> u32 {
+    DEFAULT_PER_PAGE
+}
+
+impl Default for PageQuery {
+    fn default() -> Self {
+        PageQuery {
+            page: DEFAULT_PAGE,
+            per_page: DEFAULT_PER_PAGE,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum PageQueryError {
+    #[error("page must be greater than 0")]
+    InvalidPage,
+    #[error("per_page must be between 1 and {max}, got {per_page}")]
+    InvalidPerPage { per_page: u32, max: u32 },
+    #[error("limit must be between 1 and {max}, got {limit}")]
+    InvalidLimit { limit: u32, max: u32 },
+    #[error("malformed cursor")]
+    InvalidCursor,
+}
+
+impl crate::response::error::ResponseError for PageQueryError {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        match self {
+            PageQueryError::InvalidPerPage { .. } | PageQueryError::InvalidLimit { .. } => {
+                crate::response::error::ErrorCode::ValidationFailed
+            }
+            PageQueryError::InvalidPage | PageQueryError::InvalidCursor => {
+                crate::response::error::ErrorCode::BadRequest
+            }
+        }
+    }
+
+    // names the offending parameter and the allowed maximum
+    fn field_errors(&self) -> Option<Vec<crate::response::error::FieldError>> {
+        let (field, max) = match self {
+            PageQueryError::InvalidPerPage { max, .. } => ("per_page", max),
+            PageQueryError::InvalidLimit { max, .. } => ("limit", max),
+            PageQueryError::InvalidPage | PageQueryError::InvalidCursor => return None,
+        };
+        Some(vec![crate::response::error::FieldError::new(
+            field,
+            format!("must be between 1 and {}", max).as_str(),
+        )])
+    }
+}
+
+impl PageQuery {
+    pub fn validate(&self) -> Result<(), PageQueryError> {
+        if self.page == 0 {
+            return Err(PageQueryError::InvalidPage);
+        }
+        let max = max_per_page();
+        if self.per_page == 0 || self.per_page > max {
+            return Err(PageQueryError::InvalidPerPage {
+                per_page: self.per_page,
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    // number of items to skip to reach the requested page
+    pub fn offset(&self) -> u64 {
+        u64::from(self.page.saturating_sub(1)) * u64::from(self.per_page)
+    }
+}
+
+// One page of a list endpoint, sent as `data` of the ApiSuccess envelope. `items` is always
+// present, an empty list is sent as `[]` and never omitted or `null`.
+#[derive(Debug, serde::Serialize)]
+pub struct Paginated<T> {
+    pub items: Vec<T>,
+    pub page: u32,
+    pub per_page: u32,
+    pub total: u64,
+    // 0 when there are no items at all, clients should not expect a single empty page
+    pub total_pages: u64,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, query: &PageQuery, total: u64) -> Self {
+        Paginated {
+            items,
+            page: query.page,
+            per_page: query.per_page,
+            total,
+            total_pages: total.div_ceil(u64::from(query.per_page.max(1))),
+        }
+    }
+
+    // `{"items": [], "total": 0, "total_pages": 0, ..}`
+    pub fn empty(query: &PageQuery) -> Self {
+        Paginated::new(vec![], query, 0)
+    }
+
+    // RFC 5988 `Link` header value with the first, prev, next and last pages of `uri` (the
+    // request uri), other query parameters are kept. prev and next are left out on the first
+    // and last page, there is no last page when there are no items.
+    pub fn links(&self, uri: &axum::http::Uri) -> String {
+        let params: Vec<&str> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !pair.is_empty() && name != "page" && name != "per_page"
+            })
+            .collect();
+        let link = |page: u64, rel: &str| {
+            let mut query = params.join("&");
+            if !query.is_empty() {
+                query.push('&');
+            }
+            format!(
+                "<{}?{}page={}&per_page={}>; rel=\"{}\"",
+                uri.path(),
+                query,
+                page,
+                self.per_page,
+                rel
+            )
+        };
+
+        let page = u64::from(self.page);
+        let mut links = vec![link(1, "first")];
+        if page > 1 {
+            links.push(link(page - 1, "prev"));
+        }
+        if page < self.total_pages {
+            links.push(link(page + 1, "next"));
+        }
+        if self.total_pages > 0 {
+            links.push(link(self.total_pages, "last"));
+        }
+        links.join(", ")
+    }
+}
+
+// `?cursor=..&limit=..` of the cursor paginated list endpoints, `cursor` is the opaque
+// `next_cursor` of the previous page and is absent for the first page
+#[derive(Debug, serde::Deserialize)]
+pub struct CursorQuery {
+    pub cursor: Option<String>,
+    #[serde(default = "default_per_page")]
+    pub limit: u32,
+}
+
+impl CursorQuery {
+    pub fn validate(&self) -> Result<(), PageQueryError> {
+        let max = max_per_page();
+        if self.limit == 0 || self.limit > max {
+            return Err(PageQueryError::InvalidLimit {
+                limit: self.limit,
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    // key of the last item of the previous page
+    pub fn after(&self) -> Result<Option<String>, PageQueryError> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+}
+
+pub fn encode_cursor(key: &str) -> String {
+    use base64::Engine;
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(key)
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<String, PageQueryError> {
+    use base64::Engine;
+
+    base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(PageQueryError::InvalidCursor)
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> CursorPage<T> {
+    // `last_key` is the key of the last item, None when there are no more pages
+    pub fn new(items: Vec<T>, last_key: Option<&str>) -> Self {
+        CursorPage {
+            items,
+            next_cursor: last_key.map(encode_cursor),
+        }
+    }
+}
+
+// `?fields=id,name` of the read endpoints, asks for a partial response with just those fields
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct FieldsQuery {
+    pub fields: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("unknown fields requested: {}", .0.join(", "))]
+pub struct UnknownFields(pub Vec<String>);
+
+impl crate::response::error::ResponseError for UnknownFields {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::BadRequest
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+impl FieldsQuery {
+    fn names(&self) -> Option<Vec<&str>> {
+        self.fields.as_deref().map(|fields| {
+            fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .collect()
+        })
+    }
+
+    // Serializes `data` keeping only the requested top level fields, of every item when `data`
+    // is a list. Without `fields` the value is returned whole. Names not in `known` (the fields
+    // of the response type, optional ones included) are rejected, a typo should not silently
+    // return nothing.
+    pub fn select<T: serde::Serialize>(
+        &self,
+        data: &T,
+        known: &[&str],
+    ) -> Result<serde_json::Value, UnknownFields> {
+        let value = serde_json::to_value(data).unwrap_or(serde_json::Value::Null);
+        let names = match self.names() {
+            Some(names) if !names.is_empty() => names,
+            _ => return Ok(value),
+        };
+        let unknown: Vec<String> = names
+            .iter()
+            .filter(|name| !known.contains(name))
+            .map(|name| name.to_string())
+            .collect();
+        if !unknown.is_empty() {
+            return Err(UnknownFields(unknown));
+        }
+        Ok(match value {
+            serde_json::Value::Array(items) => serde_json::Value::Array(
+                items.into_iter().map(|item| prune(item, &names)).collect(),
+            ),
+            value => prune(value, &names),
+        })
+    }
+}
+
+fn prune(value: serde_json::Value, names: &[&str]) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(mut object) => {
+            object.retain(|key, _| names.contains(&key.as_str()));
+            serde_json::Value::Object(object)
+        }
+        // only objects have fields to pick from
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // convention: no items means no pages, `total_pages` is 0 rather than a single empty page
+    #[test]
+    fn empty_page_serializes_items_as_empty_array() {
+        let page = Paginated::<String>::empty(&PageQuery::default());
+        assert_eq!(
+            serde_json::to_value(page).unwrap(),
+            serde_json::json!({
+                "items": [],
+                "page": DEFAULT_PAGE,
+                "per_page": DEFAULT_PER_PAGE,
+                "total": 0,
+                "total_pages": 0,
+            })
+        );
+    }
+
+    fn query(per_page: u32) -> PageQuery {
+        PageQuery { page: 1, per_page }
+    }
+
+    #[test]
+    fn per_page_at_the_maximum_is_accepted() {
+        assert!(query(DEFAULT_MAX_PER_PAGE).validate().is_ok());
+        assert!(query(1).validate().is_ok());
+    }
+
+    #[test]
+    fn per_page_over_the_maximum_names_the_field() {
+        let err = query(DEFAULT_MAX_PER_PAGE + 1).validate().unwrap_err();
+        let api_error = crate::response::error::ApiError::new(&err, "test.list", "trace-1");
+        assert_eq!(
+            api_error.code,
+            crate::response::error::ErrorCode::ValidationFailed
+        );
+        let field_errors = api_error.field_errors.unwrap();
+        assert_eq!(field_errors.len(), 1);
+        assert_eq!(field_errors[0].field, "per_page");
+        assert_eq!(field_errors[0].message, "must be between 1 and 100");
+    }
+
+    #[test]
+    fn per_page_of_zero_is_rejected() {
+        assert!(matches!(
+            query(0).validate(),
+            Err(PageQueryError::InvalidPerPage { per_page: 0, .. })
+        ));
+    }
+
+    fn cursor_query(cursor: Option<&str>, limit: u32) -> CursorQuery {
+        CursorQuery {
+            cursor: cursor.map(str::to_string),
+            limit,
+        }
+    }
+
+    #[test]
+    fn cursor_round_trips_the_last_key() {
+        let page = CursorPage::new(vec![1, 2], Some("user/42"));
+        let next = page.next_cursor.unwrap();
+        assert!(!next.contains('/'), "{}", next);
+        assert_eq!(
+            cursor_query(Some(next.as_str()), 10).after().unwrap(),
+            Some("user/42".to_string())
+        );
+        assert_eq!(cursor_query(None, 10).after().unwrap(), None);
+        assert!(CursorPage::new(vec![1], None).next_cursor.is_none());
+    }
+
+    #[test]
+    fn malformed_cursor_is_rejected() {
+        // not base64 at all, and base64 of bytes which are not utf-8
+        for cursor in ["not a cursor!", "_w"] {
+            assert!(matches!(
+                cursor_query(Some(cursor), 10).after(),
+                Err(PageQueryError::InvalidCursor)
+            ));
+        }
+        let api_error = crate::response::error::ApiError::new(
+            &PageQueryError::InvalidCursor,
+            "test.list",
+            "trace-1",
+        );
+        assert_eq!(
+            api_error.code,
+            crate::response::error::ErrorCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn limit_outside_the_bounds_is_rejected() {
+        assert!(cursor_query(None, DEFAULT_MAX_PER_PAGE).validate().is_ok());
+        for limit in [0, DEFAULT_MAX_PER_PAGE + 1] {
+            let err = cursor_query(None, limit).validate().unwrap_err();
+            assert!(matches!(err, PageQueryError::InvalidLimit { .. }));
+            let api_error = crate::response::error::ApiError::new(&err, "test.list", "trace-1");
+            assert_eq!(
+                api_error.code,
+                crate::response::error::ErrorCode::ValidationFailed
+            );
+            assert_eq!(api_error.field_errors.unwrap()[0].field, "limit");
+        }
+    }
+
+    fn fields(fields: Option<&str>) -> super::FieldsQuery {
+        super::FieldsQuery {
+            fields: fields.map(str::to_string),
+        }
+    }
+
+    const KNOWN: &[&str] = &["id", "name", "created_at"];
+
+    #[test]
+    fn fields_keep_only_the_requested_keys() {
+        let items = serde_json::json!([
+            { "id": 1, "name": "a", "created_at": "2024-01-01" },
+            { "id": 2, "name": "b", "created_at": "2024-01-02" },
+        ]);
+        assert_eq!(
+            fields(Some("id")).select(&items, KNOWN).unwrap(),
+            serde_json::json!([{ "id": 1 }, { "id": 2 }])
+        );
+        assert_eq!(fields(None).select(&items, KNOWN).unwrap(), items);
+    }
+
+    #[test]
+    fn unknown_field_is_bad_request() {
+        use crate::response::error::ResponseError;
+
+        let data = serde_json::json!({ "id": 1, "name": "a" });
+        let err = fields(Some("id,secret")).select(&data, KNOWN).unwrap_err();
+        assert_eq!(err.0, vec!["secret".to_string()]);
+        assert_eq!(
+            err.error_code(),
+            crate::response::error::ErrorCode::BadRequest
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use crate::response::error::FieldError;
+
+pub const MAX_BATCH_SIZE: usize = 100;
+
+static EMAIL_REGEX: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+
+fn email_regex() -> &'static regex::Regex {
+    EMAIL_REGEX.get_or_init(|| {
+        regex::Regex::new(r"^[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}$")
+            .expect("email regex is valid")
+    })
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("request validation failed")]
+pub struct ValidationError(pub Vec<FieldError>);
+
+impl crate::response::error::ResponseError for ValidationError {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::ValidationFailed
+    }
+
+    fn field_errors(&self) -> Option<Vec<FieldError>> {
+        Some(self.0.clone())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("batch of {size} items exceeds the maximum of {max}")]
+pub struct BatchTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl crate::response::error::ResponseError for BatchTooLarge {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::BadRequest
+    }
+}
+
+pub fn batch_size<T>(items: &[T]) -> Result<(), BatchTooLarge> {
+    if items.len() > MAX_BATCH_SIZE {
+        return Err(BatchTooLarge {
+            size: items.len(),
+            max: MAX_BATCH_SIZE,
+        });
+    }
+    Ok(())
+}
+
+// Collects every failed rule instead of stopping at the first one, so the client gets all the
+// problems with its input in a single response
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn check(&mut self, field: &str, valid: bool, message: &str) -> &mut Self {
+        if !valid {
+            self.errors.push(FieldError::new(field, message));
+        }
+        self
+    }
+
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        self.check(
+            field,
+            email_regex().is_match(value),
+            "must be a valid email address",
+        )
+    }
+
+    // length is counted in characters, not bytes
+    pub fn length(&mut self, field: &str, value: &str, min: usize, max: usize) -> &mut Self {
+        let length = value.chars().count();
+        self.check(
+            field,
+            (min..=max).contains(&length),
+            format!("must be between {} and {} characters", min, max).as_str(),
+        )
+    }
+
+    // runs the rules of one element of a batch, its field names get the element's index as
+    // prefix (`[2].name`) so the client knows which element was bad
+    pub fn item(&mut self, index: usize, rules: impl FnOnce(&mut Validator)) -> &mut Self {
+        let mut item = Validator::new();
+        rules(&mut item);
+        self.errors
+            .extend(item.errors.into_iter().map(|err| FieldError {
+                field: format!("[{}].{}", index, err.field),
+                message: err.message,
+            }));
+        self
+    }
+
+    pub fn finish(&mut self) -> Result<(), ValidationError> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError(std::mem::take(&mut self.errors)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn batch_of_the_maximum_size_is_accepted() {
+        assert!(super::batch_size(&[0; super::MAX_BATCH_SIZE]).is_ok());
+        let err = super::batch_size(&[0; super::MAX_BATCH_SIZE + 1]).unwrap_err();
+        assert_eq!(err.size, 101);
+        assert_eq!(err.max, 100);
+    }
+
+    #[test]
+    fn item_errors_are_prefixed_with_their_index() {
+        let names = ["ok", ""];
+        let mut validator = super::Validator::new();
+        for (index, name) in names.iter().enumerate() {
+            validator.item(index, |item| {
+                item.length("name", name, 1, 10).email("email", "nope");
+            });
+        }
+        let err = validator.finish().unwrap_err();
+        let fields: Vec<&str> = err.0.iter().map(|err| err.field.as_str()).collect();
+        assert_eq!(fields, ["[0].email", "[1].name", "[1].email"]);
+    }
+
+    #[test]
+    fn valid_input_passes() {
+        let mut validator = super::Validator::new();
+        validator
+            .email("email", "someone@example.com")
+            .length("name", "ünïcode", 1, 7);
+        assert!(validator.finish().is_ok());
+    }
+}
@@ -1,5 +1,8 @@
 pub mod controller;
 pub mod listener;
+pub mod metrics;
+pub mod middleware;
+pub mod response;
 pub mod router;
 pub mod routes;
 pub mod settings;
@@ -2,6 +2,33 @@ pub async fn http_main(settings: &crate::settings::Settings) -> std::io::Result<
     // settings up the telemetry
     // read the config with some db pool settings
     // create the http server with axum
+    crate::response::error::set_verbosity(settings.response.verbosity);
+    crate::response::error::set_details_format(settings.response.details_format);
+    crate::response::error::set_timestamp_format(settings.response.timestamp_format);
+    crate::response::error::set_mask_server_errors(settings.response.mask_server_errors);
+    crate::response::error::set_retry_after_jitter(
+        settings.response.retry_after_jitter_secs,
+        settings
+            .response
+            .retry_after_jitter_seed
+            .unwrap_or_else(|| {
+                std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_nanos() as u64)
+                    .unwrap_or_default()
+            }),
+    );
+    if let Some(support_url) = settings.response.support_url.clone() {
+        crate::response::error::set_enrichers(vec![Box::new(crate::response::error::SupportUrl(
+            support_url,
+        ))]);
+    }
+    crate::controller::types::set_max_per_page(settings.pagination.max_per_page);
+    crate::middleware::request_id::set_id_header(
+        settings.request_id.header.as_str(),
+        settings.request_id.format,
+    )
+    .expect("unexpected value for `request-id.header`");
     let listener = tokio::net::TcpListener::bind(std::net::SocketAddr::new(
         settings
             .service
@@ -18,6 +45,123 @@ pub async fn http_main(settings: &crate::settings::Settings) -> std::io::Result<
         listener.local_addr().unwrap().port()
     );
 
-    axum::serve(listener, crate::router::routes().await).await?;
-    Ok(())
+    crate::controller::health::set_ready(true);
+    serve(
+        listener,
+        crate::router::routes(settings).await,
+        shutdown_signal(),
+        std::time::Duration::from_secs(settings.service.drain_timeout_secs),
+    )
+    .await
+}
+
+// Serves until `shutdown` resolves, then stops accepting connections and lets the in-flight
+// requests finish. Whatever is still running after `drain_timeout` gets dropped.
+pub async fn serve<F>(
+    listener: tokio::net::TcpListener,
+    router: axum::Router,
+    shutdown: F,
+    drain_timeout: std::time::Duration,
+) -> std::io::Result<()>
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let (shutdown_tx, mut shutdown_rx) = tokio::sync::watch::channel(false);
+    let signal = async move {
+        shutdown.await;
+        // load balancers stop sending traffic once the readiness check fails
+        crate::controller::health::set_ready(false);
+        let _ = shutdown_tx.send(true);
+    };
+    let server = async move {
+        // the peer address is what the rate limiter keys anonymous clients on
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+        )
+        .with_graceful_shutdown(signal)
+        .await
+    };
+    let drain = async move {
+        if shutdown_rx.wait_for(|shutdown| *shutdown).await.is_err() {
+            // the server stopped without a shutdown signal
+            std::future::pending::<()>().await;
+        }
+        tokio::time::sleep(drain_timeout).await;
+    };
+
+    tokio::select! {
+        result = server => result,
+        _ = drain => {
+            eprintln!("##### drain timeout of {:?} elapsed, dropping in-flight requests", drain_timeout);
+            Ok(())
+        }
+    }
+}
+
+// Resolves on SIGINT (ctrl-c) or SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install the ctrl-c handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install the SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    eprintln!("##### Shutdown signal received, draining connections");
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn serve_stops_cleanly_when_shutdown_resolves() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = crate::router::routes(&crate::router::tests::settings()).await;
+        let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(super::serve(
+            listener,
+            router,
+            async move {
+                let _ = shutdown_rx.await;
+            },
+            std::time::Duration::from_secs(5),
+        ));
+
+        // the server answers while running
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /v1/api/health/ HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        shutdown_tx.send(()).unwrap();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+            .await
+            .expect("server did not stop after the shutdown signal")
+            .unwrap();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+    }
 }
@@ -0,0 +1,64 @@
+// Process wide request and error counters, rendered in the Prometheus text format by
+// `GET /metrics`. Kept in memory, they start from zero on every restart like any counter.
+
+type Counters<K> = std::sync::Mutex<std::collections::BTreeMap<K, u64>>;
+
+// (path, status)
+static REQUESTS: Counters<(String, u16)> = std::sync::Mutex::new(std::collections::BTreeMap::new());
+// ErrorCode variant name
+static ERRORS: Counters<String> = std::sync::Mutex::new(std::collections::BTreeMap::new());
+
+fn increment<K: Ord>(counters: &Counters<K>, key: K) {
+    let mut counters = counters.lock().unwrap_or_else(|err| err.into_inner());
+    *counters.entry(key).or_default() += 1;
+}
+
+// `path` is the route pattern (`/users/:id`) and not the raw path, to keep the label set small
+pub fn record_request(path: &str, status: u16) {
+    increment(&REQUESTS, (path.to_string(), status));
+}
+
+pub fn record_error(code: crate::response::error::ErrorCode) {
+    increment(&ERRORS, format!("{:?}", code));
+}
+
+pub fn render() -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP http_requests_total Requests served by path and status"
+    );
+    let _ = writeln!(out, "# TYPE http_requests_total counter");
+    for ((path, status), count) in REQUESTS
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .iter()
+    {
+        let _ = writeln!(
+            out,
+            "http_requests_total{{path=\"{}\",status=\"{}\"}} {}",
+            escape(path),
+            status,
+            count
+        );
+    }
+    let _ = writeln!(
+        out,
+        "# HELP http_errors_total Error responses by error code"
+    );
+    let _ = writeln!(out, "# TYPE http_errors_total counter");
+    for (code, count) in ERRORS.lock().unwrap_or_else(|err| err.into_inner()).iter() {
+        let _ = writeln!(out, "http_errors_total{{code=\"{}\"}} {}", code, count);
+    }
+    out
+}
+
+// label values are quoted strings, backslash, quote and newline have to be escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,276 @@
+// Claims of a verified bearer token, put into the request extensions by the `auth` middleware
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Claims {
+    // user id
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    // tenant of the user on multi tenant deployments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("missing bearer token")]
+    MissingToken,
+    #[error("bearer token has expired")]
+    ExpiredToken,
+    #[error("invalid bearer token: {0}")]
+    InvalidToken(jsonwebtoken::errors::Error),
+    #[error("jwt secret is not configured")]
+    NotConfigured,
+}
+
+impl crate::response::error::ResponseError for AuthError {
+    // a missing secret is the server's misconfiguration, not a bad token of the client
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        match self {
+            AuthError::NotConfigured => crate::response::error::ErrorCode::InternalServerError,
+            _ => crate::response::error::ErrorCode::UnAuthorized,
+        }
+    }
+
+    fn user_message(&self) -> String {
+        match self {
+            AuthError::MissingToken => "Authentication is required".to_string(),
+            AuthError::ExpiredToken => "Your session has expired, please log in again".to_string(),
+            AuthError::InvalidToken(_) => "Invalid authentication token".to_string(),
+            AuthError::NotConfigured => "An unexpected error occurred".to_string(),
+        }
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+
+    // RFC 6750, tells the client a new token is needed rather than just any token
+    fn extra_headers(&self) -> Vec<(String, String)> {
+        match self {
+            AuthError::ExpiredToken | AuthError::InvalidToken(_) => vec![(
+                axum::http::header::WWW_AUTHENTICATE.to_string(),
+                format!(
+                    "{}, error=\"invalid_token\"",
+                    crate::response::error::WWW_AUTHENTICATE_CHALLENGE
+                ),
+            )],
+            AuthError::MissingToken | AuthError::NotConfigured => vec![],
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Auth {
+    key: Option<std::sync::Arc<jsonwebtoken::DecodingKey>>,
+    validation: jsonwebtoken::Validation,
+}
+
+impl Auth {
+    pub fn new(settings: &crate::settings::AuthSettings) -> Self {
+        let key = if settings.jwt_secret.is_empty() {
+            tracing::warn!("auth.jwt-secret is empty, every bearer token will be rejected");
+            None
+        } else {
+            Some(std::sync::Arc::new(jsonwebtoken::DecodingKey::from_secret(
+                settings.jwt_secret.as_bytes(),
+            )))
+        };
+        Auth {
+            key,
+            validation: jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS256),
+        }
+    }
+
+    pub fn verify(&self, headers: &axum::http::HeaderMap) -> Result<Claims, AuthError> {
+        let token = headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or(AuthError::MissingToken)?;
+        let key = self.key.as_ref().ok_or(AuthError::NotConfigured)?;
+
+        jsonwebtoken::decode::<Claims>(token, key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|err| match err.kind() {
+                jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+                _ => AuthError::InvalidToken(err),
+            })
+    }
+}
+
+// Verifies the `Authorization: Bearer <jwt>` header, requests without a valid token are answered
+// with an UnAuthorized error before reaching the handler. Meant to be added with `route_layer`
+// to the routers which need authentication:
+// `.route_layer(axum::middleware::from_fn_with_state(Auth::new(&settings.auth), auth))`
+pub async fn auth(
+    axum::extract::State(auth): axum::extract::State<Auth>,
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    match auth.verify(request.headers()) {
+        Ok(claims) => {
+            let context = crate::response::error::ErrorContext {
+                user_id: Some(claims.sub.clone()),
+                tenant_id: claims.tenant.clone(),
+            };
+            request.extensions_mut().insert(claims);
+            crate::response::error::scope_context(context, next.run(request)).await
+        }
+        Err(err) => {
+            let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+            crate::response::error::response(&err, "request.auth", request_id.as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+
+    fn now() -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn token(secret: &str, exp: u64) -> String {
+        let claims = Claims {
+            sub: "user-1".to_string(),
+            exp,
+            roles: vec!["admin".to_string()],
+            tenant: Some("tenant-1".to_string()),
+        };
+        jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    fn auth(secret: &str) -> Auth {
+        Auth::new(&crate::settings::AuthSettings {
+            jwt_secret: secret.to_string(),
+        })
+    }
+
+    fn bearer(token: &str) -> axum::http::HeaderMap {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            axum::http::header::AUTHORIZATION,
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn valid_token_is_accepted() {
+        let claims = auth(SECRET)
+            .verify(&bearer(token(SECRET, now() + 3600).as_str()))
+            .unwrap();
+        assert_eq!(claims.sub, "user-1");
+        assert_eq!(claims.roles, vec!["admin".to_string()]);
+        assert_eq!(claims.tenant.as_deref(), Some("tenant-1"));
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        // well past the default leeway of a minute
+        let err = auth(SECRET)
+            .verify(&bearer(token(SECRET, now() - 3600).as_str()))
+            .unwrap_err();
+        assert!(matches!(err, AuthError::ExpiredToken), "{:?}", err);
+    }
+
+    #[test]
+    fn tampered_token_is_rejected() {
+        let signed = token(SECRET, now() + 3600);
+        // signed with another secret
+        let err = auth(SECRET)
+            .verify(&bearer(token("other-secret", now() + 3600).as_str()))
+            .unwrap_err();
+        assert!(matches!(err, AuthError::InvalidToken(_)), "{:?}", err);
+
+        // payload swapped for another one, the signature no longer matches
+        let mut parts: Vec<&str> = signed.split('.').collect();
+        let forged = token("other-secret", now() + 7200);
+        parts[1] = forged.split('.').nth(1).unwrap();
+        let err = auth(SECRET)
+            .verify(&bearer(parts.join(".").as_str()))
+            .unwrap_err();
+        assert!(matches!(err, AuthError::InvalidToken(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn missing_token_is_rejected() {
+        let err = auth(SECRET)
+            .verify(&axum::http::HeaderMap::new())
+            .unwrap_err();
+        assert!(matches!(err, AuthError::MissingToken), "{:?}", err);
+    }
+
+    #[test]
+    fn missing_secret_is_a_server_error() {
+        use crate::response::error::ResponseError;
+
+        let err = auth("")
+            .verify(&bearer(token(SECRET, now() + 3600).as_str()))
+            .unwrap_err();
+        assert!(matches!(err, AuthError::NotConfigured), "{:?}", err);
+        assert_eq!(
+            err.status_code(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(err.extra_headers().is_empty());
+    }
+
+    fn protected() -> axum::Router {
+        axum::Router::new()
+            .route(
+                "/me",
+                axum::routing::get(|| async { crate::response::success("ok") }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                auth(SECRET),
+                super::auth,
+            ))
+    }
+
+    async fn get_me(token: &str) -> axum::response::Response {
+        let request = axum::http::Request::get("/me")
+            .header(
+                axum::http::header::AUTHORIZATION,
+                format!("Bearer {}", token),
+            )
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(protected(), request).await
+    }
+
+    #[tokio::test]
+    async fn valid_token_reaches_the_handler() {
+        let response = get_me(token(SECRET, now() + 3600).as_str()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn invalid_token_gets_401_envelope_with_challenge() {
+        let response = get_me(token("other-secret", now() + 3600).as_str()).await;
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[axum::http::header::WWW_AUTHENTICATE],
+            "Bearer realm=\"api\", error=\"invalid_token\""
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "UNAUTHORIZED");
+        assert_eq!(body["error"]["operation"], "request.auth");
+        assert_eq!(body["error"]["message"], "Invalid authentication token");
+    }
+}
@@ -0,0 +1,86 @@
+pub const DEFAULT_MAX_BODY_SIZE: usize = 256 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+#[error("request body of {size} bytes exceeds the limit of {limit} bytes")]
+pub struct PayloadTooLarge {
+    pub size: u64,
+    pub limit: usize,
+}
+
+impl crate::response::error::ResponseError for PayloadTooLarge {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::PayloadTooLarge
+    }
+
+    fn user_message(&self) -> String {
+        format!("Request body must not be larger than {} bytes", self.limit)
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+// Rejects requests announcing a body above `limit` through our error envelope. Bodies without a
+// `Content-Length` are capped by axum's `DefaultBodyLimit` set next to this layer.
+pub async fn body_limit(
+    axum::extract::State(limit): axum::extract::State<usize>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let size = request
+        .headers()
+        .get(axum::http::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    match size {
+        Some(size) if size > limit as u64 => {
+            let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+            crate::response::error::response(
+                &PayloadTooLarge { size, limit },
+                "request.body_limit",
+                request_id.as_str(),
+            )
+        }
+        _ => next.run(request).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn router() -> axum::Router {
+        axum::Router::new().route(
+            "/items",
+            axum::routing::post(|body: String| async move { crate::response::success(body.len()) }),
+        )
+    }
+
+    #[tokio::test]
+    async fn announced_body_over_the_limit_gets_the_413_envelope() {
+        let limit = crate::middleware::body_limit::DEFAULT_MAX_BODY_SIZE;
+        let request = axum::http::Request::post("/items")
+            .header(axum::http::header::CONTENT_LENGTH, limit + 1)
+            .body(axum::body::Body::from(vec![b'a'; limit + 1]))
+            .unwrap();
+        let response = crate::router::tests::send_through(router(), request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "PAYLOAD_TOO_LARGE");
+        assert_eq!(body["error"]["operation"], "request.body_limit");
+    }
+
+    #[tokio::test]
+    async fn body_at_the_limit_passes() {
+        let limit = crate::middleware::body_limit::DEFAULT_MAX_BODY_SIZE;
+        let request = axum::http::Request::post("/items")
+            .header(axum::http::header::CONTENT_LENGTH, limit)
+            .body(axum::body::Body::from(vec![b'a'; limit]))
+            .unwrap();
+        let response = crate::router::tests::send_through(router(), request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], limit);
+    }
+}
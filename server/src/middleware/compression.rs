@@ -0,0 +1,112 @@
+use tower_http::compression::Predicate;
+
+pub const DEFAULT_MIN_SIZE: u16 = 1024;
+
+pub type CompressionPredicate = tower_http::compression::predicate::And<
+    tower_http::compression::predicate::And<
+        tower_http::compression::predicate::SizeAbove,
+        tower_http::compression::predicate::NotForContentType,
+    >,
+    NotForDigest,
+>;
+
+// Skips responses carrying a `Digest` header, it covers the uncompressed bytes and would no
+// longer match the body on the wire
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NotForDigest;
+
+impl Predicate for NotForDigest {
+    fn should_compress<B>(&self, response: &axum::http::Response<B>) -> bool
+    where
+        B: axum::body::HttpBody,
+    {
+        !response
+            .headers()
+            .contains_key(crate::response::DIGEST_HEADER)
+    }
+}
+
+// Compresses success and error bodies with gzip/deflate when the client's `Accept-Encoding`
+// allows it and the body is larger than `min_size` bytes, small bodies and bodies with a
+// `Digest` are sent as is
+pub fn layer(min_size: u16) -> tower_http::compression::CompressionLayer<CompressionPredicate> {
+    tower_http::compression::CompressionLayer::new().compress_when(
+        tower_http::compression::predicate::SizeAbove::new(min_size)
+            .and(tower_http::compression::predicate::NotForContentType::IMAGES)
+            .and(NotForDigest),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    fn router() -> axum::Router {
+        // well over the minimum size once serialized
+        let items: Vec<String> = (0..200).map(|i| format!("item-{}", i)).collect();
+        let digested = items.clone();
+        axum::Router::new()
+            .route(
+                "/digest",
+                axum::routing::get(move || async move {
+                    crate::response::success_with_digest(digested)
+                }),
+            )
+            .route(
+                "/plain",
+                axum::routing::get(move || async move { crate::response::success(items) }),
+            )
+            .route(
+                "/small",
+                axum::routing::get(|| async { crate::response::success("ok") }),
+            )
+            .layer(super::layer(super::DEFAULT_MIN_SIZE))
+    }
+
+    fn get(uri: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get(uri)
+            .header(axum::http::header::ACCEPT_ENCODING, "gzip")
+            .body(axum::body::Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn digest_matches_the_bytes_on_the_wire() {
+        use base64::Engine;
+        use sha2::Digest;
+
+        let response = crate::router::tests::send(router(), get("/digest")).await;
+        assert!(!response
+            .headers()
+            .contains_key(axum::http::header::CONTENT_ENCODING));
+        let digest = response.headers()[crate::response::DIGEST_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let recomputed = format!(
+            "sha-256={}",
+            base64::engine::general_purpose::STANDARD.encode(sha2::Sha256::digest(&bytes))
+        );
+        assert_eq!(digest, recomputed);
+    }
+
+    #[tokio::test]
+    async fn large_bodies_without_a_digest_are_compressed() {
+        let response = crate::router::tests::send(router(), get("/plain")).await;
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_ENCODING],
+            "gzip"
+        );
+    }
+
+    #[tokio::test]
+    async fn bodies_below_the_minimum_size_are_not_compressed() {
+        let response = crate::router::tests::send(router(), get("/small")).await;
+        assert!(!response
+            .headers()
+            .contains_key(axum::http::header::CONTENT_ENCODING));
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "ok");
+    }
+}
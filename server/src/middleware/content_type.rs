@@ -0,0 +1,123 @@
+#[derive(thiserror::Error, Debug)]
+#[error("expected an application/json body, got `{0}`")]
+pub struct NotJson(pub String);
+
+impl crate::response::error::ResponseError for NotJson {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::UnsupportedMediaType
+    }
+
+    fn user_message(&self) -> String {
+        "Request body must be sent as application/json".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+// `application/json`, optionally with parameters, or any `+json` type
+fn is_json(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+// Rejects POST, PUT and PATCH requests whose `Content-Type` is not JSON with
+// UnsupportedMediaType before any extractor tries to parse the body. Meant to be added with
+// `route_layer` to the routers taking json bodies:
+// `.route_layer(axum::middleware::from_fn(require_json))`
+pub async fn require_json(
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let has_body = matches!(
+        *request.method(),
+        axum::http::Method::POST | axum::http::Method::PUT | axum::http::Method::PATCH
+    );
+    let content_type = request
+        .headers()
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    if has_body && !is_json(content_type) {
+        let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+        return crate::response::error::response(
+            &NotJson(content_type.to_string()),
+            "request.content_type",
+            request_id.as_str(),
+        );
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    #[tokio::test]
+    async fn unsupported_content_type_is_a_415() {
+        let response = crate::response::error::response(
+            &super::NotJson("text/plain".to_string()),
+            "test.op",
+            "trace-1",
+        );
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["code"], "UNSUPPORTED_MEDIA_TYPE");
+        assert_eq!(body["error"]["code_id"], 1015);
+    }
+
+    fn router() -> axum::Router {
+        axum::Router::new()
+            .route(
+                "/items",
+                axum::routing::post(|| async { crate::response::success("created") })
+                    .get(|| async { crate::response::success("listed") }),
+            )
+            .route_layer(axum::middleware::from_fn(super::require_json))
+    }
+
+    async fn send(method: &str, content_type: Option<&str>) -> axum::response::Response {
+        let mut request = axum::http::Request::builder().method(method).uri("/items");
+        if let Some(content_type) = content_type {
+            request = request.header(axum::http::header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body(axum::body::Body::from("{}")).unwrap();
+        crate::router::tests::send_through(router(), request).await
+    }
+
+    #[tokio::test]
+    async fn post_without_json_is_rejected_before_the_handler() {
+        for content_type in [Some("text/plain"), None] {
+            let response = send("POST", content_type).await;
+            assert_eq!(
+                response.status(),
+                axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "{:?}",
+                content_type
+            );
+            let body = crate::router::tests::body_json(response).await;
+            assert_eq!(body["error"]["code"], "UNSUPPORTED_MEDIA_TYPE");
+            assert_eq!(body["error"]["operation"], "request.content_type");
+        }
+    }
+
+    #[tokio::test]
+    async fn json_bodies_and_reads_pass() {
+        for (method, content_type) in [
+            ("POST", Some("application/json; charset=utf-8")),
+            ("POST", Some("application/merge-patch+json")),
+            ("GET", None),
+        ] {
+            let response = send(method, content_type).await;
+            assert_eq!(response.status(), axum::http::StatusCode::OK, "{}", method);
+        }
+    }
+}
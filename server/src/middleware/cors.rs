@@ -0,0 +1,103 @@
+// CORS headers and `OPTIONS` preflight handling for browser clients, configured from the
+// `[cors]` settings. Entries which are not valid origins, methods or header names are skipped.
+pub fn layer(settings: &crate::settings::CorsSettings) -> tower_http::cors::CorsLayer {
+    tower_http::cors::CorsLayer::new()
+        .allow_origin(allow_origin(&settings.allowed_origins))
+        .allow_methods(allow_methods(&settings.allowed_methods))
+        .allow_headers(allow_headers(&settings.allowed_headers))
+        .expose_headers([
+            axum::http::HeaderName::from_static(crate::middleware::request_id::REQUEST_ID_HEADER),
+            axum::http::HeaderName::from_static(crate::middleware::deprecation::DEPRECATION_HEADER),
+            axum::http::HeaderName::from_static(crate::middleware::deprecation::SUNSET_HEADER),
+            axum::http::header::LINK,
+        ])
+}
+
+fn is_wildcard(values: &[String]) -> bool {
+    values.iter().any(|value| value == "*")
+}
+
+fn allow_origin(origins: &[String]) -> tower_http::cors::AllowOrigin {
+    if is_wildcard(origins) {
+        return tower_http::cors::AllowOrigin::any();
+    }
+    tower_http::cors::AllowOrigin::list(origins.iter().filter_map(|origin| {
+        axum::http::HeaderValue::from_str(origin)
+            .map_err(|err| tracing::warn!("skipping cors origin `{}`: {}", origin, err))
+            .ok()
+    }))
+}
+
+fn allow_methods(methods: &[String]) -> tower_http::cors::AllowMethods {
+    if is_wildcard(methods) {
+        return tower_http::cors::AllowMethods::any();
+    }
+    tower_http::cors::AllowMethods::list(methods.iter().filter_map(|method| {
+        axum::http::Method::from_bytes(method.as_bytes())
+            .map_err(|err| tracing::warn!("skipping cors method `{}`: {}", method, err))
+            .ok()
+    }))
+}
+
+fn allow_headers(headers: &[String]) -> tower_http::cors::AllowHeaders {
+    if is_wildcard(headers) {
+        return tower_http::cors::AllowHeaders::any();
+    }
+    tower_http::cors::AllowHeaders::list(headers.iter().filter_map(|header| {
+        axum::http::HeaderName::from_bytes(header.as_bytes())
+            .map_err(|err| tracing::warn!("skipping cors header `{}`: {}", header, err))
+            .ok()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    async fn preflight(origin: &str) -> axum::response::Response {
+        let mut settings = crate::router::tests::settings();
+        settings.cors = crate::settings::CorsSettings {
+            allowed_origins: vec!["https://app.example".to_string()],
+            allowed_methods: vec!["GET".to_string(), "POST".to_string()],
+            allowed_headers: vec!["content-type".to_string(), "not a header".to_string()],
+        };
+        let request = axum::http::Request::builder()
+            .method(axum::http::Method::OPTIONS)
+            .uri("/v1/api/health/")
+            .header(axum::http::header::ORIGIN, origin)
+            .header(axum::http::header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(
+                axum::http::header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "content-type",
+            )
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(crate::router::routes(&settings).await, request).await
+    }
+
+    #[tokio::test]
+    async fn preflight_from_an_allowed_origin_is_answered() {
+        let response = preflight("https://app.example").await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(
+            headers[axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example"
+        );
+        let methods = headers[axum::http::header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("POST"), "{}", methods);
+        // the invalid entry is skipped, the valid one still applies
+        assert_eq!(
+            headers[axum::http::header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+    }
+
+    #[tokio::test]
+    async fn preflight_from_another_origin_is_not_allowed() {
+        let response = preflight("https://evil.example").await;
+        assert!(!response
+            .headers()
+            .contains_key(axum::http::header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+}
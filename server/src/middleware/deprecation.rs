@@ -0,0 +1,94 @@
+pub const DEPRECATION_HEADER: &str = "deprecation";
+pub const SUNSET_HEADER: &str = "sunset";
+
+// When a deprecated endpoint goes away, sent as the `Sunset` header
+#[derive(Debug, Clone, Default)]
+pub struct Deprecation {
+    pub sunset: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl Deprecation {
+    pub fn new(sunset: Option<chrono::DateTime<chrono::Utc>>) -> Self {
+        Deprecation { sunset }
+    }
+}
+
+// Marks every response of the routes it is added to as deprecated with `Deprecation: true` and,
+// when known, the `Sunset` date. Bodies stay untouched. Opt in per router with `route_layer`:
+// `.route_layer(axum::middleware::from_fn_with_state(Deprecation::new(Some(date)), deprecation))`
+pub async fn deprecation(
+    axum::extract::State(deprecation): axum::extract::State<Deprecation>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        DEPRECATION_HEADER,
+        axum::http::HeaderValue::from_static("true"),
+    );
+    if let Some(sunset) = deprecation.sunset {
+        // HTTP-date, always GMT
+        let sunset = sunset.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(value) = axum::http::HeaderValue::from_str(sunset.as_str()) {
+            headers.insert(SUNSET_HEADER, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    fn router() -> axum::Router {
+        use chrono::TimeZone;
+
+        let sunset = chrono::Utc
+            .with_ymd_and_hms(2025, 6, 30, 23, 59, 59)
+            .single()
+            .unwrap();
+        let old = axum::Router::new()
+            .route(
+                "/v1/old",
+                axum::routing::get(|| async { crate::response::success("old") }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                super::Deprecation::new(Some(sunset)),
+                super::deprecation,
+            ));
+        axum::Router::new()
+            .route(
+                "/v1/new",
+                axum::routing::get(|| async { crate::response::success("new") }),
+            )
+            .merge(old)
+    }
+
+    async fn get(uri: &str) -> axum::response::Response {
+        let request = axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(router(), request).await
+    }
+
+    #[tokio::test]
+    async fn marked_route_gets_deprecation_and_sunset() {
+        let response = get("/v1/old").await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.headers()[super::DEPRECATION_HEADER], "true");
+        assert_eq!(
+            response.headers()[super::SUNSET_HEADER],
+            "Mon, 30 Jun 2025 23:59:59 GMT"
+        );
+        // the body is left as the handler built it
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "old");
+    }
+
+    #[tokio::test]
+    async fn other_routes_are_not_marked() {
+        let response = get("/v1/new").await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert!(!response.headers().contains_key(super::DEPRECATION_HEADER));
+        assert!(!response.headers().contains_key(super::SUNSET_HEADER));
+    }
+}
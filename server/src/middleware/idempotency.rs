@@ -0,0 +1,524 @@
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+pub const DEFAULT_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+pub const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum IdempotencyError {
+    #[error("a request with idempotency key `{0}` is still being processed")]
+    InProgress(String),
+    #[error("idempotency key `{0}` was already used with a different request body")]
+    KeyReused(String),
+    #[error("failed to buffer the request body: {0}")]
+    RequestBody(axum::Error),
+    #[error("failed to buffer the response body: {0}")]
+    Body(axum::Error),
+}
+
+impl crate::response::error::ResponseError for IdempotencyError {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        match self {
+            IdempotencyError::InProgress(_) => crate::response::error::ErrorCode::Conflict,
+            IdempotencyError::KeyReused(_) => {
+                crate::response::error::ErrorCode::UnprocessableEntity
+            }
+            IdempotencyError::RequestBody(_) => crate::response::error::ErrorCode::BadRequest,
+            IdempotencyError::Body(_) => crate::response::error::ErrorCode::InternalServerError,
+        }
+    }
+
+    fn user_message(&self) -> String {
+        match self {
+            IdempotencyError::InProgress(_) => {
+                "A request with this idempotency key is already being processed".to_string()
+            }
+            IdempotencyError::KeyReused(_) => {
+                "This idempotency key was already used for a different request".to_string()
+            }
+            IdempotencyError::RequestBody(_) => "The request body could not be read".to_string(),
+            IdempotencyError::Body(_) => "An unexpected error occurred".to_string(),
+        }
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+struct CachedResponse {
+    status: axum::http::StatusCode,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+}
+
+impl CachedResponse {
+    fn replay(&self) -> axum::response::Response {
+        let mut response = axum::response::Response::new(axum::body::Body::from(self.body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+    }
+}
+
+type BodyHash = [u8; 32];
+
+struct Entry {
+    // None while the first request is still being handled
+    response: Option<CachedResponse>,
+    // sha-256 of the request body the key was first used with
+    body_hash: BodyHash,
+    stored_at: std::time::Instant,
+}
+
+// In memory store of the responses sent for an `Idempotency-Key`, per process only so it
+// needs to move to a shared store once there is more than one instance
+#[derive(Clone)]
+pub struct IdempotencyStore {
+    entries: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, Entry>>>,
+    ttl: std::time::Duration,
+    // keys kept at most, the oldest one is dropped to make room for a new one
+    max_entries: usize,
+    // largest request and response body buffered, should match `service.max-body-size`
+    max_body_size: usize,
+}
+
+enum Lookup {
+    Replay(axum::response::Response),
+    InProgress,
+    Mismatch,
+    Run,
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: std::time::Duration) -> Self {
+        IdempotencyStore {
+            entries: Default::default(),
+            ttl,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_body_size: crate::middleware::body_limit::DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    // Claims `key` for the calling request unless it is already known, all in one lock so two
+    // concurrent requests can never both run the handler. A known key only answers requests
+    // with the body it was first used with.
+    fn lookup(&self, key: &str, body_hash: BodyHash) -> Lookup {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        let ttl = self.ttl;
+        entries.retain(|_, entry| entry.stored_at.elapsed() < ttl);
+
+        match entries.get(key) {
+            Some(entry) if entry.body_hash != body_hash => Lookup::Mismatch,
+            Some(Entry {
+                response: Some(response),
+                ..
+            }) => Lookup::Replay(response.replay()),
+            Some(Entry { response: None, .. }) => Lookup::InProgress,
+            None => {
+                if entries.len() >= self.max_entries {
+                    // a claim evicted while its handler runs just ends up not cached
+                    let oldest = entries
+                        .iter()
+                        .min_by_key(|(_, entry)| entry.stored_at)
+                        .map(|(key, _)| key.clone());
+                    if let Some(oldest) = oldest {
+                        entries.remove(&oldest);
+                    }
+                }
+                entries.insert(
+                    key.to_string(),
+                    Entry {
+                        response: None,
+                        body_hash,
+                        stored_at: std::time::Instant::now(),
+                    },
+                );
+                Lookup::Run
+            }
+        }
+    }
+
+    fn store(&self, key: &str, response: Option<CachedResponse>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|err| err.into_inner());
+        match (response, entries.get_mut(key)) {
+            (Some(response), Some(entry)) => {
+                entry.response = Some(response);
+                entry.stored_at = std::time::Instant::now();
+            }
+            // the claim expired while the handler ran, nothing to answer repeats with
+            (Some(_), None) => {}
+            (None, _) => {
+                entries.remove(key);
+            }
+        }
+    }
+}
+
+// Releases a claimed key unless the handler finished, so a handler future dropped half way
+// (client disconnect, outer timeout, panic) does not leave the key answering Conflict
+struct Claim {
+    store: IdempotencyStore,
+    key: String,
+    done: bool,
+}
+
+impl Claim {
+    fn finish(mut self, response: Option<CachedResponse>) {
+        self.store.store(self.key.as_str(), response);
+        self.done = true;
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        if !self.done {
+            self.store.store(self.key.as_str(), None);
+        }
+    }
+}
+
+impl Default for IdempotencyStore {
+    fn default() -> Self {
+        IdempotencyStore::new(DEFAULT_TTL)
+    }
+}
+
+enum Buffered {
+    Complete(axum::body::Bytes),
+    // over the limit, the bytes read so far and the rest of the stream as one body again
+    TooLarge { read: usize, body: axum::body::Body },
+}
+
+// Reads `body` into memory unless it turns out larger than `limit`. Needed on top of
+// `body_limit`, which only sees bodies announcing their size through `Content-Length`.
+async fn buffer(body: axum::body::Body, limit: usize) -> Result<Buffered, axum::Error> {
+    use futures_util::StreamExt;
+
+    let mut stream = body.into_data_stream();
+    let mut buffered = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        buffered.extend_from_slice(&chunk?);
+        if buffered.len() > limit {
+            let read = buffered.len();
+            let prefix = futures_util::stream::once(async move {
+                Ok::<_, axum::Error>(axum::body::Bytes::from(buffered))
+            });
+            return Ok(Buffered::TooLarge {
+                read,
+                body: axum::body::Body::from_stream(prefix.chain(stream)),
+            });
+        }
+    }
+    Ok(Buffered::Complete(buffered.into()))
+}
+
+// Makes retried create requests safe: the first response for an `Idempotency-Key` is kept for
+// the store's ttl and sent again for every repeat with the same body, a repeat arriving while
+// the first one is still running gets a Conflict and one with a different body an
+// UnprocessableEntity. Server errors are not kept so the client can retry them. Keys are per
+// caller (user or peer address, see `rate_limit::client_key`). Requests without the header pass
+// through untouched. Request bodies over the store's `max_body_size` get PayloadTooLarge, responses
+// over it are sent but not kept. Meant to be added with `route_layer`, before `auth` so it runs
+// inside of it:
+// `.route_layer(axum::middleware::from_fn_with_state(IdempotencyStore::default(), idempotency))`
+pub async fn idempotency(
+    axum::extract::State(store): axum::extract::State<IdempotencyStore>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use sha2::Digest;
+
+    let key = match request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+    {
+        // the same key sent to two different endpoints are two different operations
+        Some(key) => format!(
+            "{} {} {} {}",
+            crate::middleware::rate_limit::client_key(&request),
+            request.method(),
+            request.uri().path(),
+            key
+        ),
+        None => return next.run(request).await,
+    };
+    let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+
+    // buffered to compare it with the body of the first request, then handed on as is
+    let (parts, body) = request.into_parts();
+    let body = match buffer(body, store.max_body_size).await {
+        Ok(Buffered::Complete(body)) => body,
+        Ok(Buffered::TooLarge { read, .. }) => {
+            return crate::response::error::response(
+                &crate::middleware::body_limit::PayloadTooLarge {
+                    // a streamed body has no known size, at least this much was sent
+                    size: read as u64,
+                    limit: store.max_body_size,
+                },
+                "request.idempotency",
+                request_id.as_str(),
+            );
+        }
+        Err(err) => {
+            return crate::response::error::response(
+                &IdempotencyError::RequestBody(err),
+                "request.idempotency",
+                request_id.as_str(),
+            )
+        }
+    };
+    let body_hash: BodyHash = sha2::Sha256::digest(&body).into();
+    let request = axum::extract::Request::from_parts(parts, axum::body::Body::from(body));
+
+    let error = match store.lookup(key.as_str(), body_hash) {
+        Lookup::Replay(response) => return response,
+        Lookup::InProgress => IdempotencyError::InProgress(key),
+        Lookup::Mismatch => IdempotencyError::KeyReused(key),
+        Lookup::Run => {
+            let claim = Claim {
+                store: store.clone(),
+                key,
+                done: false,
+            };
+            let limit = store.max_body_size;
+            return run(claim, request, next, request_id, limit).await;
+        }
+    };
+    crate::response::error::response(&error, "request.idempotency", request_id.as_str())
+}
+
+async fn run(
+    claim: Claim,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+    request_id: crate::middleware::request_id::RequestId,
+    limit: usize,
+) -> axum::response::Response {
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = match buffer(body, limit).await {
+        Ok(Buffered::Complete(body)) => body,
+        Ok(Buffered::TooLarge { body, .. }) => {
+            // too large to keep, the key is released so a repeat runs the handler again
+            tracing::warn!("idempotent response over {} bytes is not kept", limit);
+            claim.finish(None);
+            return axum::response::Response::from_parts(parts, body);
+        }
+        Err(err) => {
+            claim.finish(None);
+            return crate::response::error::response(
+                &IdempotencyError::Body(err),
+                "request.idempotency",
+                request_id.as_str(),
+            );
+        }
+    };
+
+    let response = CachedResponse {
+        status: parts.status,
+        headers: parts.headers,
+        body,
+    };
+    let replay = response.replay();
+    if response.status.is_server_error() {
+        claim.finish(None);
+    } else {
+        claim.finish(Some(response));
+    }
+    replay
+}
+
+#[cfg(test)]
+mod tests {
+    fn router(created: std::sync::Arc<std::sync::atomic::AtomicUsize>) -> axum::Router {
+        router_with(created, super::IdempotencyStore::default())
+    }
+
+    // creates an item per request it runs, a `slow` body never finishes
+    fn router_with(
+        created: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+        store: super::IdempotencyStore,
+    ) -> axum::Router {
+        let create = move |body: String| async move {
+            if body == "slow" {
+                std::future::pending::<()>().await;
+            }
+            let id = created.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+            crate::response::created(
+                serde_json::json!({ "id": id, "name": body }),
+                format!("/items/{}", id).as_str(),
+            )
+        };
+        axum::Router::new()
+            .route("/items", axum::routing::post(create))
+            .route_layer(axum::middleware::from_fn_with_state(
+                store,
+                super::idempotency,
+            ))
+            .route_layer(axum::middleware::from_fn(as_user))
+    }
+
+    // stands in for `auth`, the `x-user` header becomes the claims
+    async fn as_user(
+        mut request: axum::extract::Request,
+        next: axum::middleware::Next,
+    ) -> axum::response::Response {
+        if let Some(user) = request
+            .headers()
+            .get("x-user")
+            .and_then(|value| value.to_str().ok())
+        {
+            let claims = crate::middleware::auth::Claims {
+                sub: user.to_string(),
+                exp: 0,
+                roles: vec![],
+                tenant: None,
+            };
+            request.extensions_mut().insert(claims);
+        }
+        next.run(request).await
+    }
+
+    fn post(key: &str, user: &str, body: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::post("/items")
+            .header(super::IDEMPOTENCY_KEY_HEADER, key)
+            .header("x-user", user)
+            .body(axum::body::Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn send(
+        router: &axum::Router,
+        request: axum::http::Request<axum::body::Body>,
+    ) -> (u16, serde_json::Value) {
+        let response = crate::router::tests::send(router.clone(), request).await;
+        let status = response.status().as_u16();
+        (status, crate::router::tests::body_json(response).await)
+    }
+
+    #[tokio::test]
+    async fn repeated_key_returns_the_cached_response() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = router(created.clone());
+
+        let first = send(&router, post("key-1", "alice", "a")).await;
+        let second = send(&router, post("key-1", "alice", "a")).await;
+        assert_eq!(first.0, 201);
+        assert_eq!(first, second);
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn distinct_key_creates_anew() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = router(created.clone());
+
+        let first = send(&router, post("key-1", "alice", "a")).await;
+        let second = send(&router, post("key-2", "alice", "a")).await;
+        assert_eq!(first.1["data"]["id"], 1);
+        assert_eq!(second.1["data"]["id"], 2);
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn same_key_of_another_caller_is_not_shared() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = router(created.clone());
+
+        let alice = send(&router, post("key-1", "alice", "a")).await;
+        let bob = send(&router, post("key-1", "bob", "a")).await;
+        assert_eq!(alice.1["data"]["id"], 1);
+        assert_eq!(bob.1["data"]["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn reused_key_with_another_body_is_rejected() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = router(created.clone());
+
+        send(&router, post("key-1", "alice", "a")).await;
+        let (status, body) = send(&router, post("key-1", "alice", "b")).await;
+        assert_eq!(status, 422);
+        assert_eq!(body["error"]["code"], "UNPROCESSABLE_ENTITY");
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_request_releases_its_key() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let router = router(created.clone());
+
+        // the client gives up on a request which never finishes
+        let abandoned = tokio::time::timeout(
+            std::time::Duration::from_millis(50),
+            send(&router, post("key-1", "alice", "slow")),
+        )
+        .await;
+        assert!(abandoned.is_err());
+
+        // the key is free again instead of answering Conflict for the whole ttl
+        let (status, _) = send(&router, post("key-1", "alice", "a")).await;
+        assert_eq!(status, 201);
+    }
+
+    #[tokio::test]
+    async fn oversized_chunked_body_is_rejected() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let store = super::IdempotencyStore::default().with_max_body_size(16);
+        let router = router_with(created.clone(), store);
+
+        // no Content-Length, so `body_limit` cannot see the size up front
+        let chunks = ["0123456789", "0123456789"]
+            .map(|chunk| Ok::<_, std::io::Error>(axum::body::Bytes::from(chunk)));
+        let request = axum::http::Request::post("/items")
+            .header(super::IDEMPOTENCY_KEY_HEADER, "key-1")
+            .header("x-user", "alice")
+            .body(axum::body::Body::from_stream(futures_util::stream::iter(
+                chunks,
+            )))
+            .unwrap();
+        let (status, body) = send(&router, request).await;
+        assert_eq!(status, 413);
+        assert_eq!(body["error"]["code"], "PAYLOAD_TOO_LARGE");
+        assert_eq!(created.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn oversized_response_is_sent_but_not_kept() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        // the request fits, the json envelope of the response does not
+        let store = super::IdempotencyStore::default().with_max_body_size(16);
+        let router = router_with(created.clone(), store);
+
+        let first = send(&router, post("key-1", "alice", "a")).await;
+        let second = send(&router, post("key-1", "alice", "a")).await;
+        assert_eq!(first.0, 201);
+        assert_eq!(first.1["data"]["id"], 1);
+        assert_eq!(second.1["data"]["id"], 2);
+    }
+
+    #[tokio::test]
+    async fn oldest_key_is_evicted_when_full() {
+        let created = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let store = super::IdempotencyStore::default().with_max_entries(1);
+        let router = router_with(created.clone(), store);
+
+        send(&router, post("key-1", "alice", "a")).await;
+        send(&router, post("key-2", "alice", "a")).await;
+        // key-2 is still kept, key-1 made room for it
+        let (_, key_2) = send(&router, post("key-2", "alice", "a")).await;
+        assert_eq!(key_2["data"]["id"], 2);
+        let (_, key_1) = send(&router, post("key-1", "alice", "a")).await;
+        assert_eq!(key_1["data"]["id"], 3);
+    }
+}
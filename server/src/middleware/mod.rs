@@ -0,0 +1,13 @@
+pub mod auth;
+pub mod body_limit;
+pub mod compression;
+pub mod content_type;
+pub mod cors;
+pub mod deprecation;
+pub mod idempotency;
+pub mod negotiate;
+pub mod rate_limit;
+pub mod request_id;
+pub mod response_time;
+pub mod timeout;
+pub mod trace;
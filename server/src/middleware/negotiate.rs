@@ -0,0 +1,54 @@
+// Picks the response body format from the `Accept` header and the pretty flag for everything
+// rendered by the handler
+pub async fn negotiate(
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let format = crate::response::format::Format::from_request(request.uri(), request.headers());
+    crate::response::format::scope(format, next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    async fn get(uri: &str, pretty_header: Option<&str>) -> axum::body::Bytes {
+        let router = axum::Router::new().route(
+            "/item",
+            axum::routing::get(|| async {
+                crate::response::success(serde_json::json!({ "id": 1 }))
+            }),
+        );
+        let mut request = axum::http::Request::get(uri);
+        if let Some(value) = pretty_header {
+            request = request.header(crate::response::format::PRETTY_HEADER, value);
+        }
+        let request = request.body(axum::body::Body::empty()).unwrap();
+        let response = crate::router::tests::send_through(router, request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn pretty_query_or_header_indents_the_body() {
+        for bytes in [
+            get("/item?pretty=true", None).await,
+            get("/item", Some("true")).await,
+        ] {
+            let body = std::str::from_utf8(&bytes).unwrap();
+            assert!(body.contains("\n  \"success\": true"), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn body_is_compact_by_default() {
+        let bytes = get("/item", None).await;
+        let body = std::str::from_utf8(&bytes).unwrap();
+        assert!(!body.contains('\n'), "{}", body);
+        assert!(body.starts_with("{\"success\":true"), "{}", body);
+    }
+}
@@ -0,0 +1,228 @@
+// buckets idle for longer than a window are full again, they get dropped once the map grows
+// past this many clients
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(thiserror::Error, Debug)]
+#[error("rate limit exceeded for `{client}`")]
+pub struct RateLimited {
+    pub client: String,
+    pub retry_after: std::time::Duration,
+}
+
+impl crate::response::error::ResponseError for RateLimited {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::TooManyRequests
+    }
+
+    fn user_message(&self) -> String {
+        "Too many requests, please slow down".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        Some(self.retry_after)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: std::time::Instant,
+}
+
+// Token bucket per client: every client may burst up to `requests` and gets them back at an
+// even rate over `window`
+#[derive(Clone)]
+pub struct RateLimiter {
+    buckets: std::sync::Arc<std::sync::Mutex<std::collections::HashMap<String, Bucket>>>,
+    capacity: f64,
+    // tokens per second
+    refill_rate: f64,
+    window: std::time::Duration,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &crate::settings::RateLimitSettings) -> Self {
+        let window = std::time::Duration::from_secs(settings.window_secs.max(1));
+        let capacity = f64::from(settings.requests.max(1));
+        RateLimiter {
+            buckets: Default::default(),
+            capacity,
+            refill_rate: capacity / window.as_secs_f64(),
+            window,
+        }
+    }
+
+    // takes a token from the client's bucket, on an empty bucket returns how long until the
+    // next token is available
+    pub fn check(&self, client: &str) -> Result<(), std::time::Duration> {
+        self.check_at(client, std::time::Instant::now())
+    }
+
+    fn check_at(&self, client: &str, now: std::time::Instant) -> Result<(), std::time::Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|err| err.into_inner());
+        if buckets.len() > PRUNE_THRESHOLD {
+            let window = self.window;
+            buckets.retain(|_, bucket| now.duration_since(bucket.updated) < window);
+        }
+
+        let bucket = buckets.entry(client.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate).min(self.capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(std::time::Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_rate,
+            ))
+        }
+    }
+}
+
+// The authenticated user when the `auth` middleware ran before, the peer address otherwise.
+// `auth` is a `route_layer`, so it only runs before middlewares added as `route_layer` after it.
+pub fn client_key(request: &axum::extract::Request) -> String {
+    if let Some(claims) = request
+        .extensions()
+        .get::<crate::middleware::auth::Claims>()
+    {
+        return format!("user:{}", claims.sub);
+    }
+    match request
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+    {
+        Some(axum::extract::ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "unknown".to_string(),
+    }
+}
+
+// Answers clients over their limit with TooManyRequests and a `Retry-After` header. The global
+// layer added by `routes` runs before any authentication and limits per peer address. Routers
+// with `auth` can limit per user by adding their own limiter as `route_layer` before `auth`,
+// which makes it run inside of it:
+// `.route_layer(from_fn_with_state(RateLimiter::new(..), rate_limit)).route_layer(auth..)`
+pub async fn rate_limit(
+    axum::extract::State(limiter): axum::extract::State<RateLimiter>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let client = client_key(&request);
+    match limiter.check(client.as_str()) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => {
+            let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+            crate::response::error::response(
+                &RateLimited {
+                    client,
+                    retry_after,
+                },
+                "request.rate_limit",
+                request_id.as_str(),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn limiter(requests: u32, window_secs: u64) -> super::RateLimiter {
+        super::RateLimiter::new(&crate::settings::RateLimitSettings {
+            enabled: true,
+            requests,
+            window_secs,
+        })
+    }
+
+    #[test]
+    fn request_over_the_limit_is_rejected() {
+        let limiter = limiter(3, 60);
+        let now = std::time::Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("ip:1.2.3.4", now).is_ok());
+        }
+        let retry_after = limiter.check_at("ip:1.2.3.4", now).unwrap_err();
+        // one token comes back every 20 seconds
+        assert_eq!(retry_after, std::time::Duration::from_secs(20));
+        // other clients have their own bucket
+        assert!(limiter.check_at("ip:5.6.7.8", now).is_ok());
+    }
+
+    #[test]
+    fn bucket_refills_after_the_window() {
+        let limiter = limiter(3, 60);
+        let now = std::time::Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check_at("ip:1.2.3.4", now).is_ok());
+        }
+        assert!(limiter.check_at("ip:1.2.3.4", now).is_err());
+
+        let later = now + std::time::Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check_at("ip:1.2.3.4", later).is_ok());
+        }
+        assert!(limiter.check_at("ip:1.2.3.4", later).is_err());
+    }
+
+    // authenticated routers put their limiter inside of `auth`, which keys it per user
+    #[tokio::test]
+    async fn limiter_inside_auth_limits_per_user() {
+        async fn as_user(
+            mut request: axum::extract::Request,
+            next: axum::middleware::Next,
+        ) -> axum::response::Response {
+            let user = request.headers()["x-user"].to_str().unwrap().to_string();
+            request
+                .extensions_mut()
+                .insert(crate::middleware::auth::Claims {
+                    sub: user,
+                    exp: 0,
+                    roles: vec![],
+                    tenant: None,
+                });
+            next.run(request).await
+        }
+
+        let router = axum::Router::new()
+            .route(
+                "/me",
+                axum::routing::get(|| async { crate::response::success("ok") }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                limiter(1, 60),
+                super::rate_limit,
+            ))
+            .route_layer(axum::middleware::from_fn(as_user));
+        let get = |user: &str| {
+            axum::http::Request::get("/me")
+                .header("x-user", user)
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let alice = crate::router::tests::send(router.clone(), get("alice")).await;
+        assert_eq!(alice.status(), axum::http::StatusCode::OK);
+        let alice = crate::router::tests::send(router.clone(), get("alice")).await;
+        assert_eq!(alice.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        assert!(alice
+            .headers()
+            .contains_key(axum::http::header::RETRY_AFTER));
+        let body = crate::router::tests::body_json(alice).await;
+        assert_eq!(body["error"]["code"], "TOO_MANY_REQUESTS");
+        assert_eq!(
+            body["error"]["description"],
+            "rate limit exceeded for `user:alice`"
+        );
+
+        let bob = crate::router::tests::send(router, get("bob")).await;
+        assert_eq!(bob.status(), axum::http::StatusCode::OK);
+    }
+}
@@ -0,0 +1,226 @@
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// How the incoming id header is read
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdFormat {
+    // the whole header value is the id
+    #[default]
+    Plain,
+    // W3C `traceparent` (`00-<trace-id>-<parent-id>-<flags>`), the trace-id is the id
+    Traceparent,
+}
+
+#[derive(Debug, Clone)]
+struct IdHeader {
+    name: axum::http::HeaderName,
+    format: IdFormat,
+}
+
+static ID_HEADER: std::sync::OnceLock<IdHeader> = std::sync::OnceLock::new();
+
+// Header the id is read from, `X-Request-Id` unless configured otherwise
+pub fn set_id_header(
+    name: &str,
+    format: IdFormat,
+) -> Result<(), axum::http::header::InvalidHeaderName> {
+    let name = axum::http::HeaderName::from_bytes(name.as_bytes())?;
+    let _ = ID_HEADER.set(IdHeader { name, format });
+    Ok(())
+}
+
+fn id_header() -> IdHeader {
+    ID_HEADER.get().cloned().unwrap_or(IdHeader {
+        name: axum::http::HeaderName::from_static(REQUEST_ID_HEADER),
+        format: IdFormat::Plain,
+    })
+}
+
+// longest incoming id which is reused, the id ends up in every log line and error body
+pub const MAX_ID_LEN: usize = 128;
+
+// ids are echoed into headers, logs and urls, anything but `[A-Za-z0-9._-]` is not trusted
+fn is_valid_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_ID_LEN
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+// trace-id of a `traceparent` value, None for anything malformed or the invalid all zero id
+fn parse_traceparent(value: &str) -> Option<&str> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    let is_hex = |part: &str, len: usize| {
+        part.len() == len
+            && part
+                .bytes()
+                .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    };
+    if !is_hex(version, 2)
+        || version == "ff"
+        || !is_hex(trace_id, 32)
+        || !is_hex(parent_id, 16)
+        || !is_hex(flags, 2)
+        || trace_id.bytes().all(|b| b == b'0')
+    {
+        return None;
+    }
+    Some(trace_id)
+}
+
+// Id of the current request, reused from the incoming `X-Request-Id` header if the gateway or
+// client sent a well formed one, otherwise freshly generated
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+impl RequestId {
+    pub fn from_headers(headers: &axum::http::HeaderMap) -> Self {
+        RequestId::read(headers, &id_header())
+    }
+
+    fn read(headers: &axum::http::HeaderMap, id_header: &IdHeader) -> Self {
+        headers
+            .get(&id_header.name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| match id_header.format {
+                IdFormat::Plain => Some(value),
+                IdFormat::Traceparent => parse_traceparent(value),
+            })
+            .filter(|value| is_valid_id(value))
+            .map(|value| RequestId(value.to_string()))
+            .unwrap_or_else(|| RequestId(uuid::Uuid::new_v4().to_string()))
+    }
+
+    // id resolved by the `request_id` middleware, for the middlewares running inside of it
+    pub fn from_request(request: &axum::extract::Request) -> Self {
+        request
+            .extensions()
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId::from_headers(request.headers()))
+    }
+
+    pub fn from_parts(parts: &axum::http::request::Parts) -> Self {
+        parts
+            .extensions
+            .get::<RequestId>()
+            .cloned()
+            .unwrap_or_else(|| RequestId::from_headers(&parts.headers))
+    }
+
+    // id of the request being handled, None outside of the `request_id` middleware
+    pub fn current() -> Option<Self> {
+        REQUEST_ID.try_with(|request_id| request_id.clone()).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequestParts<S> for RequestId {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        // the middleware has already resolved the id, so handlers see the same one
+        Ok(RequestId::from_parts(parts))
+    }
+}
+
+// Resolves the request id once, makes it available to the handlers and the response helpers
+// and echoes it back as the `X-Request-Id` response header, whichever header it was read from
+pub async fn request_id(
+    mut request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let request_id = RequestId::from_headers(request.headers());
+    request.extensions_mut().insert(request_id.clone());
+
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .await;
+    if let Ok(value) = axum::http::HeaderValue::from_str(request_id.as_str()) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::router::tests::{body_json, send_default};
+
+    fn with_id(id: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get("/no-such-route")
+            .header(super::REQUEST_ID_HEADER, id)
+            .body(axum::body::Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn incoming_id_is_reused_in_body_and_header() {
+        let response = send_default(with_id("gateway-id.42_a")).await;
+        assert_eq!(
+            response.headers()[super::REQUEST_ID_HEADER],
+            "gateway-id.42_a"
+        );
+        let body = body_json(response).await;
+        assert_eq!(body["error"]["trace_id"], "gateway-id.42_a");
+    }
+
+    #[tokio::test]
+    async fn malformed_id_is_replaced() {
+        for id in ["x&foo=bar", "has space", &"a".repeat(super::MAX_ID_LEN + 1)] {
+            let response = send_default(with_id(id)).await;
+            let echoed = response.headers()[super::REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert_ne!(echoed, id);
+            assert!(uuid::Uuid::parse_str(echoed.as_str()).is_ok(), "{}", echoed);
+            let body = body_json(response).await;
+            assert_eq!(body["error"]["trace_id"], echoed.as_str());
+        }
+    }
+
+    fn traceparent(value: &str) -> super::RequestId {
+        let id_header = super::IdHeader {
+            name: axum::http::HeaderName::from_static("traceparent"),
+            format: super::IdFormat::Traceparent,
+        };
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert("traceparent", value.parse().unwrap());
+        super::RequestId::read(&headers, &id_header)
+    }
+
+    #[test]
+    fn trace_id_of_a_valid_traceparent_is_the_id() {
+        let id = traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01");
+        assert_eq!(id.as_str(), "4bf92f3577b34da6a3ce929d0e0e4736");
+    }
+
+    #[test]
+    fn invalid_traceparent_gets_a_new_id() {
+        for value in [
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            let id = traceparent(value);
+            assert!(uuid::Uuid::parse_str(id.as_str()).is_ok(), "{}", value);
+        }
+    }
+}
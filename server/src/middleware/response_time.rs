@@ -0,0 +1,45 @@
+pub const RESPONSE_TIME_HEADER: &str = "x-response-time-ms";
+
+// Adds `X-Response-Time-Ms`, the time from receiving the request until the response was
+// generated, to success and error responses alike
+pub async fn response_time(
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let start = std::time::Instant::now();
+    let mut response = next.run(request).await;
+    let elapsed_ms = start.elapsed().as_millis() as u64;
+    response.headers_mut().insert(
+        RESPONSE_TIME_HEADER,
+        axum::http::HeaderValue::from(elapsed_ms),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    async fn health(response_time_header: bool) -> axum::response::Response {
+        let mut settings = crate::router::tests::settings();
+        settings.response.response_time_header = response_time_header;
+        let request = axum::http::Request::builder()
+            .uri("/v1/api/health/")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(crate::router::routes(&settings).await, request).await
+    }
+
+    #[tokio::test]
+    async fn header_is_added_when_enabled() {
+        let response = health(true).await;
+        let value = response.headers()[super::RESPONSE_TIME_HEADER]
+            .to_str()
+            .unwrap();
+        assert!(value.parse::<u64>().is_ok(), "{}", value);
+    }
+
+    #[tokio::test]
+    async fn header_is_absent_when_disabled() {
+        let response = health(false).await;
+        assert!(!response.headers().contains_key(super::RESPONSE_TIME_HEADER));
+    }
+}
@@ -0,0 +1,136 @@
+#[derive(thiserror::Error, Debug)]
+#[error("request to `{route}` did not complete within {timeout:?}")]
+pub struct RequestTimedOut {
+    pub route: String,
+    pub timeout: std::time::Duration,
+}
+
+impl crate::response::error::ResponseError for RequestTimedOut {
+    fn error_code(&self) -> crate::response::error::ErrorCode {
+        crate::response::error::ErrorCode::GatewayTimeout
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct Timeouts {
+    default: std::time::Duration,
+    routes: std::sync::Arc<std::collections::HashMap<String, std::time::Duration>>,
+}
+
+impl Timeouts {
+    pub fn new(settings: &crate::settings::TimeoutSettings) -> Self {
+        Timeouts {
+            default: std::time::Duration::from_secs(settings.default_secs),
+            routes: std::sync::Arc::new(
+                settings
+                    .routes
+                    .iter()
+                    .map(|(route, secs)| (route.clone(), std::time::Duration::from_secs(*secs)))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn for_route(&self, route: Option<&str>) -> std::time::Duration {
+        route
+            .and_then(|route| self.routes.get(route))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+// Drops the handler once it runs past the timeout of its route and answers with
+// GatewayTimeout instead, so a stuck dependency cannot hold the connection forever
+pub async fn timeout(
+    axum::extract::State(timeouts): axum::extract::State<Timeouts>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let route = request
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    let timeout = timeouts.for_route(route.as_deref());
+    let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => crate::response::error::response(
+            &RequestTimedOut {
+                route: route.unwrap_or_else(|| "unmatched".to_string()),
+                timeout,
+            },
+            "request.timeout",
+            request_id.as_str(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn settings() -> crate::settings::TimeoutSettings {
+        crate::settings::TimeoutSettings {
+            default_secs: 30,
+            routes: [("/slow".to_string(), 1)].into_iter().collect(),
+        }
+    }
+
+    fn router() -> axum::Router {
+        async fn slow() -> axum::response::Response {
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            crate::response::success("done")
+        }
+
+        axum::Router::new()
+            .route("/slow", axum::routing::get(slow))
+            .route(
+                "/fast",
+                axum::routing::get(|| async { crate::response::success("done") }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                super::Timeouts::new(&settings()),
+                super::timeout,
+            ))
+    }
+
+    fn get(uri: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get(uri)
+            .body(axum::body::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn route_overrides_the_default() {
+        let timeouts = super::Timeouts::new(&settings());
+        assert_eq!(
+            timeouts.for_route(Some("/slow")),
+            std::time::Duration::from_secs(1)
+        );
+        assert_eq!(
+            timeouts.for_route(Some("/other")),
+            std::time::Duration::from_secs(30)
+        );
+        assert_eq!(timeouts.for_route(None), std::time::Duration::from_secs(30));
+    }
+
+    // the paused clock jumps straight to the timeout instead of waiting for it
+    #[tokio::test(start_paused = true)]
+    async fn slow_handler_gets_the_gateway_timeout_envelope() {
+        let response = crate::router::tests::send(router(), get("/slow")).await;
+        assert_eq!(response.status(), axum::http::StatusCode::GATEWAY_TIMEOUT);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "GATEWAY_TIMEOUT");
+        assert_eq!(body["error"]["code_id"], 1014);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fast_handler_is_not_affected() {
+        let response = crate::router::tests::send(router(), get("/fast")).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+    }
+}
@@ -0,0 +1,145 @@
+use tracing::Instrument;
+
+// Opens one span per request carrying method, path and the request id, the handler's own
+// spans and events nest under it. Status and latency are recorded on the way out together with
+// a single `request completed` event.
+pub async fn trace(
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    let request_id = crate::middleware::request_id::RequestId::from_request(&request);
+    // route pattern, unmatched requests share one label instead of one per path
+    let route = request
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let span = tracing::info_span!(
+        "http.request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        trace_id = request_id.as_str(),
+        status = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+
+    let start = std::time::Instant::now();
+    let response = next.run(request).instrument(span.clone()).await;
+    let status = response.status().as_u16();
+    let duration_ms = start.elapsed().as_millis() as u64;
+
+    crate::metrics::record_request(route.as_str(), status);
+    span.record("status", status);
+    span.record("duration_ms", duration_ms);
+    span.in_scope(|| tracing::info!(status, duration_ms, "request completed"));
+    response
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    // name and fields of every span opened while it is the default subscriber, with the fields
+    // recorded later on
+    type Fields = std::collections::BTreeMap<String, String>;
+
+    #[derive(Clone, Default)]
+    pub struct Spans(
+        std::sync::Arc<std::sync::Mutex<std::collections::HashMap<u64, (String, Fields)>>>,
+    );
+
+    struct FieldVisitor<'a>(&'a mut Fields);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Spans {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = Fields::new();
+            attrs.record(&mut FieldVisitor(&mut fields));
+            self.0
+                .lock()
+                .unwrap()
+                .insert(id.into_u64(), (attrs.metadata().name().to_string(), fields));
+        }
+
+        fn on_record(
+            &self,
+            id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            if let Some((_, fields)) = self.0.lock().unwrap().get_mut(&id.into_u64()) {
+                values.record(&mut FieldVisitor(fields));
+            }
+        }
+    }
+
+    impl Spans {
+        // fields of the spans called `name`
+        pub fn named(&self, name: &str) -> Vec<Fields> {
+            self.0
+                .lock()
+                .unwrap()
+                .values()
+                .filter(|(span, _)| span == name)
+                .map(|(_, fields)| fields.clone())
+                .collect()
+        }
+    }
+
+    // `request` through `routes` with `Spans` as the subscriber of the (single threaded) test
+    pub async fn send_traced(
+        request: axum::http::Request<axum::body::Body>,
+    ) -> (axum::response::Response, Spans) {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let spans = Spans::default();
+        let subscriber = tracing_subscriber::registry().with(spans.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let response = crate::router::tests::send_default(request).await;
+        (response, spans)
+    }
+
+    fn get(uri: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get(uri)
+            .header("x-request-id", "req-1")
+            .body(axum::body::Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn span_records_the_request_and_its_outcome() {
+        let (response, spans) = send_traced(get("/v1/api/health/")).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let spans = spans.named("http.request");
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(span["method"], "GET");
+        assert_eq!(span["path"], "/v1/api/health/");
+        assert_eq!(span["trace_id"], "req-1");
+        assert_eq!(span["status"], "200");
+        assert!(span.contains_key("duration_ms"));
+    }
+
+    #[tokio::test]
+    async fn span_records_the_status_of_a_failing_request() {
+        let (response, spans) = send_traced(get("/v1/api/missing/")).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        let span = &spans.named("http.request")[0];
+        assert_eq!(span["path"], "/v1/api/missing/");
+        assert_eq!(span["trace_id"], "req-1");
+        assert_eq!(span["status"], "404");
+    }
+}
@@ -0,0 +1,2275 @@
+use axum::response::IntoResponse;
+
+// How much of an error is sent to the client, everything is always logged regardless
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verbosity {
+    // `message` only
+    Public,
+    // `message` and the technical `description`
+    #[default]
+    Description,
+    // everything including `details`, which may contain queries, pool state etc.
+    Full,
+}
+
+static VERBOSITY: std::sync::OnceLock<Verbosity> = std::sync::OnceLock::new();
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    let _ = VERBOSITY.set(verbosity);
+}
+
+pub fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or_default()
+}
+
+// How the technical details are sent at `Verbosity::Full`
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DetailsFormat {
+    // `details`: one string, an entry per line
+    #[default]
+    String,
+    // `details_chain`: an array with one element per entry
+    Array,
+}
+
+static DETAILS_FORMAT: std::sync::OnceLock<DetailsFormat> = std::sync::OnceLock::new();
+
+pub fn set_details_format(format: DetailsFormat) {
+    let _ = DETAILS_FORMAT.set(format);
+}
+
+pub fn details_format() -> DetailsFormat {
+    DETAILS_FORMAT.get().copied().unwrap_or_default()
+}
+
+static MASK_SERVER_ERRORS: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+pub fn set_mask_server_errors(mask: bool) {
+    let _ = MASK_SERVER_ERRORS.set(mask);
+}
+
+// On by default: 5xx bodies carry the generic message of their code instead of whatever
+// user_message the service came up with, the original is only logged
+pub fn mask_server_errors() -> bool {
+    MASK_SERVER_ERRORS.get().copied().unwrap_or(true)
+}
+
+static RETRY_AFTER_JITTER: std::sync::OnceLock<u64> = std::sync::OnceLock::new();
+// splitmix64 state, seeded once at startup. A fixed seed makes the sequence of jitters
+// reproducible.
+static JITTER_STATE: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+// Up to `window_secs` seconds are added to every `Retry-After`, so clients throttled at the same
+// moment do not all come back at the same moment. 0 (the default) sends the plain value.
+pub fn set_retry_after_jitter(window_secs: u64, seed: u64) {
+    let _ = RETRY_AFTER_JITTER.set(window_secs);
+    JITTER_STATE.store(seed, std::sync::atomic::Ordering::Relaxed);
+}
+
+// `seconds` plus a jitter in `0..=window`
+pub fn jittered_retry_after(seconds: u64) -> u64 {
+    let window = RETRY_AFTER_JITTER.get().copied().unwrap_or_default();
+    if window == 0 {
+        return seconds;
+    }
+    let state = JITTER_STATE.fetch_add(0x9E37_79B9_7F4A_7C15, std::sync::atomic::Ordering::Relaxed);
+    jitter(seconds, window, state)
+}
+
+// one splitmix64 step from `state`, kept apart from the globals so it can be tested
+fn jitter(seconds: u64, window: u64, state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    seconds.saturating_add(z % (window.saturating_add(1)))
+}
+
+// message sent to the client for an error with `status`, `mask` is `mask_server_errors()`
+fn client_message(
+    code: ErrorCode,
+    status: axum::http::StatusCode,
+    message: String,
+    mask: bool,
+) -> String {
+    if status.is_server_error() && mask {
+        code.default_message().to_string()
+    } else {
+        message
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimestampFormat {
+    // `2024-01-01T10:00:00.000Z`, readable in logs and bodies
+    #[default]
+    Rfc3339,
+    // `1704103200000`, for consumers which want to skip date parsing
+    EpochMillis,
+}
+
+static TIMESTAMP_FORMAT: std::sync::OnceLock<TimestampFormat> = std::sync::OnceLock::new();
+
+pub fn set_timestamp_format(format: TimestampFormat) {
+    let _ = TIMESTAMP_FORMAT.set(format);
+}
+
+pub fn timestamp_format() -> TimestampFormat {
+    TIMESTAMP_FORMAT.get().copied().unwrap_or_default()
+}
+
+// Serializes the error timestamp in the configured `TimestampFormat`, the field name stays the
+// same for both
+mod timestamp {
+    pub fn serialize<S: serde::Serializer>(
+        timestamp: &chrono::DateTime<chrono::Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serialize_as(timestamp, super::timestamp_format(), serializer)
+    }
+
+    pub(super) fn serialize_as<S: serde::Serializer>(
+        timestamp: &chrono::DateTime<chrono::Utc>,
+        format: super::TimestampFormat,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match format {
+            super::TimestampFormat::Rfc3339 => serializer.serialize_str(
+                timestamp
+                    .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                    .as_str(),
+            ),
+            super::TimestampFormat::EpochMillis => {
+                serializer.serialize_i64(timestamp.timestamp_millis())
+            }
+        }
+    }
+}
+
+// Who the failing request was made for, set by the `auth` middleware so errors of
+// authenticated requests can be correlated to a user and tenant
+#[derive(Debug, Clone, Default)]
+pub struct ErrorContext {
+    pub user_id: Option<String>,
+    pub tenant_id: Option<String>,
+}
+
+tokio::task_local! {
+    static CONTEXT: ErrorContext;
+}
+
+impl ErrorContext {
+    // context of the request being handled, empty outside of `scope_context`
+    pub fn current() -> Self {
+        CONTEXT
+            .try_with(|context| context.clone())
+            .unwrap_or_default()
+    }
+}
+
+pub async fn scope_context<F: std::future::Future>(context: ErrorContext, f: F) -> F::Output {
+    CONTEXT.scope(context, f).await
+}
+
+pub const WWW_AUTHENTICATE_CHALLENGE: &str = "Bearer realm=\"api\"";
+
+// Machine readable error code, serialized in SCREAMING_SNAKE_CASE (`NOT_FOUND`) so clients can
+// branch on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    NotFound,
+    BadRequest,
+    #[serde(rename = "UNAUTHORIZED")]
+    UnAuthorized,
+    Forbidden,
+    Conflict,
+    MethodNotAllowed,
+    // the `If-Match` version is stale, the resource changed since the client read it
+    PreconditionFailed,
+    ValidationFailed,
+    // well formed request which is semantically wrong, e.g. referencing something missing
+    UnprocessableEntity,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    TooManyRequests,
+    InternalServerError,
+    ServiceUnavailable,
+    // the request took longer than its timeout, usually waiting on a dependency
+    GatewayTimeout,
+}
+
+impl ErrorCode {
+    // every variant, keep in sync with the enum when adding one, `registry` is built from it
+    pub const ALL: [ErrorCode; 15] = [
+        ErrorCode::NotFound,
+        ErrorCode::BadRequest,
+        ErrorCode::UnAuthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::Conflict,
+        ErrorCode::MethodNotAllowed,
+        ErrorCode::PreconditionFailed,
+        ErrorCode::ValidationFailed,
+        ErrorCode::UnprocessableEntity,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::UnsupportedMediaType,
+        ErrorCode::TooManyRequests,
+        ErrorCode::InternalServerError,
+        ErrorCode::ServiceUnavailable,
+        ErrorCode::GatewayTimeout,
+    ];
+
+    pub fn default_message(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "The requested resource was not found",
+            ErrorCode::BadRequest => "The request is malformed",
+            ErrorCode::UnAuthorized => "Authentication is required",
+            ErrorCode::Forbidden => "You are not allowed to perform this action",
+            ErrorCode::Conflict => "The resource conflicts with an existing one",
+            ErrorCode::MethodNotAllowed => "The method is not allowed for this resource",
+            ErrorCode::PreconditionFailed => "The resource was modified in the meantime",
+            ErrorCode::ValidationFailed => "One or more fields are invalid",
+            ErrorCode::UnprocessableEntity => "The request could not be processed",
+            ErrorCode::PayloadTooLarge => "The request body is too large",
+            ErrorCode::UnsupportedMediaType => "The request body has an unsupported content type",
+            ErrorCode::TooManyRequests => "Too many requests, please slow down",
+            ErrorCode::InternalServerError => "An unexpected error occurred",
+            ErrorCode::ServiceUnavailable => "Service is temporarily unavailable",
+            ErrorCode::GatewayTimeout => "The request took too long to complete",
+        }
+    }
+
+    // Stable numeric id for clients which key off integers. Ids are part of the API contract,
+    // never reassign one, new variants get the next free number.
+    pub fn numeric(&self) -> u32 {
+        match self {
+            ErrorCode::NotFound => 1001,
+            ErrorCode::BadRequest => 1002,
+            ErrorCode::UnAuthorized => 1003,
+            ErrorCode::Forbidden => 1004,
+            ErrorCode::Conflict => 1005,
+            ErrorCode::ValidationFailed => 1006,
+            ErrorCode::TooManyRequests => 1007,
+            ErrorCode::InternalServerError => 1008,
+            ErrorCode::ServiceUnavailable => 1009,
+            ErrorCode::PayloadTooLarge => 1010,
+            ErrorCode::UnprocessableEntity => 1011,
+            ErrorCode::MethodNotAllowed => 1012,
+            ErrorCode::PreconditionFailed => 1013,
+            ErrorCode::GatewayTimeout => 1014,
+            ErrorCode::UnsupportedMediaType => 1015,
+        }
+    }
+
+    // Server side failures and rate limiting may pass on a later attempt, any other client
+    // error fails the same way until the request changes
+    pub fn is_retryable(&self) -> bool {
+        self.status_code().is_server_error() || *self == ErrorCode::TooManyRequests
+    }
+
+    // Canonical gRPC status code (google.rpc.Code), so a gRPC adapter reports the same
+    // taxonomy as the http api
+    pub fn grpc_status(&self) -> i32 {
+        match self {
+            // INVALID_ARGUMENT
+            ErrorCode::BadRequest
+            | ErrorCode::ValidationFailed
+            | ErrorCode::PayloadTooLarge
+            | ErrorCode::UnsupportedMediaType => 3,
+            // DEADLINE_EXCEEDED
+            ErrorCode::GatewayTimeout => 4,
+            // NOT_FOUND
+            ErrorCode::NotFound => 5,
+            // ALREADY_EXISTS
+            ErrorCode::Conflict => 6,
+            // PERMISSION_DENIED
+            ErrorCode::Forbidden => 7,
+            // RESOURCE_EXHAUSTED
+            ErrorCode::TooManyRequests => 8,
+            // FAILED_PRECONDITION
+            ErrorCode::UnprocessableEntity | ErrorCode::PreconditionFailed => 9,
+            // UNIMPLEMENTED
+            ErrorCode::MethodNotAllowed => 12,
+            // INTERNAL
+            ErrorCode::InternalServerError => 13,
+            // UNAVAILABLE
+            ErrorCode::ServiceUnavailable => 14,
+            // UNAUTHENTICATED
+            ErrorCode::UnAuthorized => 16,
+        }
+    }
+
+    pub fn status_code(&self) -> axum::http::StatusCode {
+        match self {
+            ErrorCode::NotFound => axum::http::StatusCode::NOT_FOUND,
+            ErrorCode::BadRequest => axum::http::StatusCode::BAD_REQUEST,
+            ErrorCode::UnAuthorized => axum::http::StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden => axum::http::StatusCode::FORBIDDEN,
+            ErrorCode::Conflict => axum::http::StatusCode::CONFLICT,
+            ErrorCode::MethodNotAllowed => axum::http::StatusCode::METHOD_NOT_ALLOWED,
+            ErrorCode::PreconditionFailed => axum::http::StatusCode::PRECONDITION_FAILED,
+            ErrorCode::ValidationFailed => axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::UnprocessableEntity => axum::http::StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::PayloadTooLarge => axum::http::StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::UnsupportedMediaType => axum::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ErrorCode::TooManyRequests => axum::http::StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InternalServerError => axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::ServiceUnavailable => axum::http::StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::GatewayTimeout => axum::http::StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+}
+
+// Documentation entry of an error code, served by `GET /v1/api/error-codes/` so clients can
+// generate their error handling from it
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorCodeInfo {
+    pub code: ErrorCode,
+    pub code_id: u32,
+    pub status: u16,
+    pub message: &'static str,
+}
+
+pub fn registry() -> Vec<ErrorCodeInfo> {
+    ErrorCode::ALL
+        .iter()
+        .map(|code| ErrorCodeInfo {
+            code: *code,
+            code_id: code.numeric(),
+            status: code.status_code().as_u16(),
+            message: code.default_message(),
+        })
+        .collect()
+}
+
+// Classification of the operation an error happened in, see `ResponseError::error_code_for`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Read,
+    Write,
+}
+
+// Implemented by every service error which can be sent back to the client.
+// Only `error_code` is required, status_code is derived from it by default.
+pub trait ResponseError: std::error::Error {
+    fn error_code(&self) -> ErrorCode;
+
+    fn status_code(&self) -> axum::http::StatusCode {
+        self.error_code().status_code()
+    }
+
+    // Code for errors whose meaning depends on what was being done when they happened, e.g. a
+    // dropped connection is worth a retry on a read but not on a write which may have landed.
+    // Only used by `response_for`, everything else keeps calling `error_code`.
+    fn error_code_for(&self, _kind: OperationKind) -> ErrorCode {
+        self.error_code()
+    }
+
+    // status of `error_code_for`, an overridden `status_code` is kept while the code is unchanged
+    fn status_code_for(&self, kind: OperationKind) -> axum::http::StatusCode {
+        let code = self.error_code_for(kind);
+        if code == self.error_code() {
+            self.status_code()
+        } else {
+            code.status_code()
+        }
+    }
+
+    fn user_message(&self) -> String {
+        self.to_string()
+    }
+
+    // user_message in the client's language, errors without a translation for `locale` fall
+    // back to the default (English) message
+    fn localized_message(&self, _locale: &str) -> String {
+        self.user_message()
+    }
+
+    // Short technical context about what failed, safe enough to show to the client
+    fn technical_description(&self) -> Option<String> {
+        None
+    }
+
+    // Internals of the failure (queries, pool state, ...), only sent at `Verbosity::Full`
+    fn technical_details(&self) -> Option<String> {
+        None
+    }
+
+    // technical_details followed by every error in the `source()` chain
+    fn details_chain(&self) -> Option<Vec<String>> {
+        let mut details: Vec<String> = self.technical_details().into_iter().collect();
+        let mut source = self.source();
+        while let Some(err) = source {
+            details.push(err.to_string());
+            source = err.source();
+        }
+        if details.is_empty() {
+            None
+        } else {
+            Some(details)
+        }
+    }
+
+    // details_chain, one entry per line
+    fn error_details(&self) -> Option<String> {
+        self.details_chain().map(|details| details.join("\n"))
+    }
+
+    // Per field failures, lets a single response report every invalid input at once
+    fn field_errors(&self) -> Option<Vec<FieldError>> {
+        None
+    }
+
+    // When set, the client is told how long to wait before retrying via `Retry-After`
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        None
+    }
+
+    // Headers the error response needs on top of the body, e.g. `WWW-Authenticate` on a 401
+    fn extra_headers(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+
+    // Whether sending the same request again can succeed, by code unless overridden
+    fn is_retryable(&self) -> bool {
+        self.error_code().is_retryable()
+    }
+
+    // `is_retryable` for errors whose retry safety depends on the operation, the counterpart of
+    // `status_code_for` and only used by `response_for`
+    fn is_retryable_for(&self, _kind: OperationKind) -> bool {
+        self.is_retryable()
+    }
+
+    // Structured remediation for clients which should not parse `message`
+    fn hints(&self) -> Option<ErrorHints> {
+        Some(ErrorHints {
+            retryable: self.is_retryable(),
+            documentation_url: None,
+        })
+    }
+
+    // Rust name of the error (`NotReady`, `RouteNotFound`, ...) for debugging tools, only sent
+    // at `Verbosity::Full`. Taken from the `Debug` output, which starts with it when derived.
+    fn variant_name(&self) -> String {
+        let debug = format!("{:?}", self);
+        debug
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ErrorHints {
+    // whether sending the same request again can succeed
+    pub retryable: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub documentation_url: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ApiErrorResponse {
+    pub success: bool,
+    pub error: ApiError,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ApiError {
+    pub code: ErrorCode,
+    pub code_id: u32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+    // `details` split per entry, sent instead of it with `DetailsFormat::Array`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details_chain: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_errors: Option<Vec<FieldError>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hints: Option<ErrorHints>,
+    // `ResponseError::variant_name`, only at `Verbosity::Full`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    // what was being done when the error happened, e.g. `user.get`
+    pub operation: String,
+    // unique per request, used to correlate the error with the server logs
+    pub trace_id: String,
+    // not sent at `Verbosity::Public`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(serialize_with = "timestamp::serialize")]
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    // echoed in the body for clients which log the body but drop the headers
+    #[serde(serialize_with = "serialize_status")]
+    pub status: axum::http::StatusCode,
+    #[serde(skip)]
+    pub retry_after: Option<std::time::Duration>,
+    #[serde(skip)]
+    pub extra_headers: Vec<(String, String)>,
+    // fields added by the `ErrorEnricher`s, sent next to the others
+    #[serde(flatten, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+fn serialize_status<S: serde::Serializer>(
+    status: &axum::http::StatusCode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u16(status.as_u16())
+}
+
+impl ApiError {
+    pub fn new<E: ResponseError + ?Sized>(err: &E, operation: &str, trace_id: &str) -> Self {
+        ApiError::with_kind(err, None, verbosity(), operation, trace_id)
+    }
+
+    // `new` at `verbosity` instead of the configured one, e.g. for internal endpoints which may
+    // always send the details
+    pub fn with_verbosity<E: ResponseError + ?Sized>(
+        err: &E,
+        verbosity: Verbosity,
+        operation: &str,
+        trace_id: &str,
+    ) -> Self {
+        ApiError::with_kind(err, None, verbosity, operation, trace_id)
+    }
+
+    // With a `kind`, the code, status and retry hint come from `error_code_for`,
+    // `status_code_for` and `is_retryable_for` instead of `error_code`, `status_code` and `hints`
+    fn with_kind<E: ResponseError + ?Sized>(
+        err: &E,
+        kind: Option<OperationKind>,
+        verbosity: Verbosity,
+        operation: &str,
+        trace_id: &str,
+    ) -> Self {
+        let (code, status) = match kind {
+            Some(kind) => (err.error_code_for(kind), err.status_code_for(kind)),
+            None => (err.error_code(), err.status_code()),
+        };
+        let mut hints = err.hints();
+        if let (Some(hints), Some(kind)) = (hints.as_mut(), kind) {
+            hints.retryable = err.is_retryable_for(kind);
+        }
+        let description = err.technical_description();
+        let details_chain = err.details_chain();
+        let details = err.error_details();
+        let message = err.user_message();
+        let context = ErrorContext::current();
+
+        crate::metrics::record_error(code);
+        // One structured event per error, so controllers do not have to log on their own. Only
+        // server errors are logged at error level, client mistakes (404s from scanners,
+        // validation failures, rate limiting) would drown the alerts otherwise.
+        macro_rules! log_error {
+            ($level:expr) => {
+                tracing::event!(
+                    $level,
+                    trace_id,
+                    operation,
+                    error_code = ?code,
+                    status = status.as_u16(),
+                    message = message.as_str(),
+                    user_id = context.user_id.as_deref().unwrap_or_default(),
+                    tenant_id = context.tenant_id.as_deref().unwrap_or_default(),
+                    description = description.as_deref().unwrap_or_default(),
+                    details = details.as_deref().unwrap_or_default(),
+                    "{}",
+                    err
+                )
+            };
+        }
+        if status.is_server_error() {
+            log_error!(tracing::Level::ERROR);
+        } else {
+            log_error!(tracing::Level::WARN);
+        }
+
+        let mut api_error = ApiError {
+            code,
+            code_id: code.numeric(),
+            message: client_message(code, status, message, mask_server_errors()),
+            description: description.filter(|_| verbosity >= Verbosity::Description),
+            details: details.filter(|_| {
+                verbosity >= Verbosity::Full && details_format() == DetailsFormat::String
+            }),
+            details_chain: details_chain.filter(|_| {
+                verbosity >= Verbosity::Full && details_format() == DetailsFormat::Array
+            }),
+            field_errors: err.field_errors(),
+            hints,
+            variant: Some(err.variant_name()).filter(|_| verbosity >= Verbosity::Full),
+            operation: operation.to_string(),
+            trace_id: trace_id.to_string(),
+            user_id: context
+                .user_id
+                .filter(|_| verbosity >= Verbosity::Description),
+            tenant_id: context
+                .tenant_id
+                .filter(|_| verbosity >= Verbosity::Description),
+            timestamp: chrono::Utc::now(),
+            status,
+            retry_after: err.retry_after(),
+            extra_headers: err.extra_headers(),
+            extensions: serde_json::Map::new(),
+        };
+        for enricher in enrichers() {
+            enricher.enrich(&mut api_error);
+        }
+        api_error
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.extra_headers
+            .push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+// Adds deployment specific fields to every error (support links, runbooks, ...) without
+// touching the error types, usually by inserting into `ApiError::extensions`
+pub trait ErrorEnricher {
+    fn enrich(&self, err: &mut ApiError);
+}
+
+static ENRICHERS: std::sync::OnceLock<Vec<Box<dyn ErrorEnricher + Send + Sync>>> =
+    std::sync::OnceLock::new();
+
+// run in order on every ApiError built after this, only the first call takes effect
+pub fn set_enrichers(enrichers: Vec<Box<dyn ErrorEnricher + Send + Sync>>) {
+    let _ = ENRICHERS.set(enrichers);
+}
+
+fn enrichers() -> &'static [Box<dyn ErrorEnricher + Send + Sync>] {
+    ENRICHERS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+// `support_url` pointing people at the support page, with the trace id so the ticket can be
+// matched with the logs
+pub struct SupportUrl(pub String);
+
+impl ErrorEnricher for SupportUrl {
+    fn enrich(&self, err: &mut ApiError) {
+        let separator = if self.0.contains('?') { '&' } else { '?' };
+        // the trace id comes from a request header, encoded so it cannot add parameters
+        let trace_id: String = form_urlencoded::byte_serialize(err.trace_id.as_bytes()).collect();
+        err.extensions.insert(
+            "support_url".to_string(),
+            serde_json::Value::String(format!("{}{}trace_id={}", self.0, separator, trace_id)),
+        );
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(mut self) -> axum::response::Response {
+        let status = self.status;
+        let retry_after = self.retry_after;
+        let extra_headers = std::mem::take(&mut self.extra_headers);
+        let mut response = match super::format::Format::current() {
+            // `message`, followed by the description on its own line when there is one
+            super::format::Format::Text => (
+                status,
+                [(
+                    axum::http::header::CONTENT_TYPE,
+                    super::format::TEXT_CONTENT_TYPE,
+                )],
+                match self.description {
+                    Some(description) => format!("{}\n{}\n", self.message, description),
+                    None => format!("{}\n", self.message),
+                },
+            )
+                .into_response(),
+            _ => super::format::render(
+                status,
+                &ApiErrorResponse {
+                    success: false,
+                    error: self,
+                },
+            ),
+        };
+
+        append_headers(&mut response, retry_after, &extra_headers);
+        response
+    }
+}
+
+// Headers every rendering of an error carries, whatever the body format
+fn append_headers(
+    response: &mut axum::response::Response,
+    retry_after: Option<std::time::Duration>,
+    extra_headers: &[(String, String)],
+) {
+    if let Some(retry_after) = retry_after {
+        // Retry-After is whole seconds, round up so clients never come back too early
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response.headers_mut().insert(
+            axum::http::header::RETRY_AFTER,
+            axum::http::HeaderValue::from(jittered_retry_after(seconds)),
+        );
+    }
+    if response.status() == axum::http::StatusCode::UNAUTHORIZED {
+        // RFC 7235, a 401 names the scheme to authenticate with. Errors can send their own
+        // challenge through `extra_headers`, which is inserted after and wins.
+        response.headers_mut().insert(
+            axum::http::header::WWW_AUTHENTICATE,
+            axum::http::HeaderValue::from_static(WWW_AUTHENTICATE_CHALLENGE),
+        );
+    }
+    for (name, value) in extra_headers.iter() {
+        // already rendering an error, a broken header is dropped rather than replacing it
+        if let Err(err) = super::insert_header(response.headers_mut(), name, value) {
+            tracing::error!("dropping error response header: {}", err);
+        }
+    }
+}
+
+pub fn localized_response<E: ResponseError>(
+    err: &E,
+    operation: &str,
+    trace_id: &str,
+    locale: &crate::controller::extract::Locale,
+) -> axum::response::Response {
+    let mut api_error = ApiError::new(err, operation, trace_id);
+    api_error.message = client_message(
+        api_error.code,
+        api_error.status,
+        err.localized_message(locale.as_str()),
+        mask_server_errors(),
+    );
+    api_error.into_response()
+}
+
+// Id of the request being handled, the same one the `http.request` span records as `trace_id`,
+// so an error body can be joined with the logs of its request. A fresh id outside of a request.
+pub fn trace_id() -> String {
+    crate::middleware::request_id::RequestId::current()
+        .map(|request_id| request_id.0)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+pub fn response<E: ResponseError>(
+    err: &E,
+    operation: &str,
+    trace_id: &str,
+) -> axum::response::Response {
+    ApiError::new(err, operation, trace_id).into_response()
+}
+
+// `response` with the code and status picked by `error_code_for` and `status_code_for`
+pub fn response_for<E: ResponseError>(
+    err: &E,
+    kind: OperationKind,
+    operation: &str,
+    trace_id: &str,
+) -> axum::response::Response {
+    ApiError::with_kind(err, Some(kind), verbosity(), operation, trace_id).into_response()
+}
+
+// Lets handlers return `Result<Response, AppError>` and use `?` on any service error instead of
+// matching and calling `response` themselves. Rendered exactly like `response`, with the id of
+// the current request as trace id.
+#[derive(Debug)]
+pub struct AppError {
+    error: Box<dyn ResponseError + Send + Sync>,
+    operation: &'static str,
+}
+
+impl AppError {
+    pub fn new<E: ResponseError + Send + Sync + 'static>(err: E, operation: &'static str) -> Self {
+        AppError {
+            error: Box::new(err),
+            operation,
+        }
+    }
+}
+
+// plain `?` has no operation to report, use `AppError::new` (via `map_err`) where it matters
+impl<E: ResponseError + Send + Sync + 'static> From<E> for AppError {
+    fn from(err: E) -> Self {
+        AppError::new(err, "request")
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        ApiError::new(self.error.as_ref(), self.operation, trace_id().as_str()).into_response()
+    }
+}
+
+// RFC 7807 `application/problem+json` rendering of an ApiError, the envelope above stays the
+// default and this is only used by the endpoints calling `response_problem`
+#[derive(Debug, serde::Serialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: ErrorCode,
+    pub status: u16,
+    pub detail: String,
+    pub instance: String,
+    #[serde(skip)]
+    pub retry_after: Option<std::time::Duration>,
+    #[serde(skip)]
+    pub extra_headers: Vec<(String, String)>,
+}
+
+impl From<ApiError> for ProblemDetails {
+    fn from(err: ApiError) -> Self {
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: err.code,
+            status: err.status.as_u16(),
+            detail: err.message,
+            instance: err.trace_id,
+            retry_after: err.retry_after,
+            extra_headers: err.extra_headers,
+        }
+    }
+}
+
+impl IntoResponse for ProblemDetails {
+    fn into_response(mut self) -> axum::response::Response {
+        let status = axum::http::StatusCode::from_u16(self.status)
+            .unwrap_or(axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        let retry_after = self.retry_after;
+        let extra_headers = std::mem::take(&mut self.extra_headers);
+        let mut response = (status, axum::Json(self)).into_response();
+        response.headers_mut().insert(
+            axum::http::header::CONTENT_TYPE,
+            axum::http::HeaderValue::from_static("application/problem+json"),
+        );
+        append_headers(&mut response, retry_after, &extra_headers);
+        response
+    }
+}
+
+pub fn response_problem<E: ResponseError>(
+    err: &E,
+    operation: &str,
+    trace_id: &str,
+) -> axum::response::Response {
+    ProblemDetails::from(ApiError::new(err, operation, trace_id)).into_response()
+}
+
+// Wraps a service error with the backtrace of the place it was created at. The backtrace is
+// only captured when `RUST_BACKTRACE` (or `RUST_LIB_BACKTRACE`) is set, otherwise nothing is
+// appended to the details.
+#[derive(Debug)]
+pub struct Backtraced<E> {
+    pub inner: E,
+    pub backtrace: std::backtrace::Backtrace,
+}
+
+impl<E> Backtraced<E> {
+    pub fn new(inner: E) -> Self {
+        Backtraced {
+            inner,
+            backtrace: std::backtrace::Backtrace::capture(),
+        }
+    }
+}
+
+impl<E> From<E> for Backtraced<E> {
+    fn from(inner: E) -> Self {
+        Backtraced::new(inner)
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for Backtraced<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Backtraced<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl<E: ResponseError> ResponseError for Backtraced<E> {
+    fn error_code(&self) -> ErrorCode {
+        self.inner.error_code()
+    }
+
+    fn status_code(&self) -> axum::http::StatusCode {
+        self.inner.status_code()
+    }
+
+    fn error_code_for(&self, kind: OperationKind) -> ErrorCode {
+        self.inner.error_code_for(kind)
+    }
+
+    fn status_code_for(&self, kind: OperationKind) -> axum::http::StatusCode {
+        self.inner.status_code_for(kind)
+    }
+
+    fn user_message(&self) -> String {
+        self.inner.user_message()
+    }
+
+    fn localized_message(&self, locale: &str) -> String {
+        self.inner.localized_message(locale)
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        self.inner.technical_description()
+    }
+
+    fn technical_details(&self) -> Option<String> {
+        self.inner.technical_details()
+    }
+
+    fn details_chain(&self) -> Option<Vec<String>> {
+        if self.backtrace.status() != std::backtrace::BacktraceStatus::Captured {
+            return self.inner.details_chain();
+        }
+        let mut details = self.inner.details_chain().unwrap_or_default();
+        details.push(format!("backtrace:\n{}", self.backtrace));
+        Some(details)
+    }
+
+    fn error_details(&self) -> Option<String> {
+        self.details_chain().map(|details| details.join("\n"))
+    }
+
+    fn field_errors(&self) -> Option<Vec<FieldError>> {
+        self.inner.field_errors()
+    }
+
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        self.inner.retry_after()
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.inner.is_retryable()
+    }
+
+    fn is_retryable_for(&self, kind: OperationKind) -> bool {
+        self.inner.is_retryable_for(kind)
+    }
+
+    fn extra_headers(&self) -> Vec<(String, String)> {
+        self.inner.extra_headers()
+    }
+
+    fn hints(&self) -> Option<ErrorHints> {
+        self.inner.hints()
+    }
+
+    fn variant_name(&self) -> String {
+        self.inner.variant_name()
+    }
+}
+
+// Wraps a service error with the `file:line` it was created at, cheaper than `Backtraced` and
+// works without `RUST_BACKTRACE`. The location is only added to the details when the
+// `debug_errors` feature is enabled. `?` into a `Located<E>` records the line of the `?`.
+#[derive(Debug)]
+pub struct Located<E> {
+    pub inner: E,
+    pub location: &'static std::panic::Location<'static>,
+}
+
+impl<E> Located<E> {
+    #[track_caller]
+    pub fn new(inner: E) -> Self {
+        Located {
+            inner,
+            location: std::panic::Location::caller(),
+        }
+    }
+}
+
+impl<E> From<E> for Located<E> {
+    #[track_caller]
+    fn from(inner: E) -> Self {
+        Located::new(inner)
+    }
+}
+
+impl<E: std::fmt::Display> std::fmt::Display for Located<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Located<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner.source()
+    }
+}
+
+impl<E: ResponseError> ResponseError for Located<E> {
+    fn error_code(&self) -> ErrorCode {
+        self.inner.error_code()
+    }
+
+    fn status_code(&self) -> axum::http::StatusCode {
+        self.inner.status_code()
+    }
+
+    fn error_code_for(&self, kind: OperationKind) -> ErrorCode {
+        self.inner.error_code_for(kind)
+    }
+
+    fn status_code_for(&self, kind: OperationKind) -> axum::http::StatusCode {
+        self.inner.status_code_for(kind)
+    }
+
+    fn user_message(&self) -> String {
+        self.inner.user_message()
+    }
+
+    fn localized_message(&self, locale: &str) -> String {
+        self.inner.localized_message(locale)
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        self.inner.technical_description()
+    }
+
+    fn technical_details(&self) -> Option<String> {
+        self.inner.technical_details()
+    }
+
+    #[cfg(feature = "debug_errors")]
+    fn details_chain(&self) -> Option<Vec<String>> {
+        let mut details = self.inner.details_chain().unwrap_or_default();
+        details.push(format!(
+            "created at {}:{}",
+            self.location.file(),
+            self.location.line()
+        ));
+        Some(details)
+    }
+
+    #[cfg(not(feature = "debug_errors"))]
+    fn details_chain(&self) -> Option<Vec<String>> {
+        self.inner.details_chain()
+    }
+
+    fn error_details(&self) -> Option<String> {
+        self.details_chain().map(|details| details.join("\n"))
+    }
+
+    fn field_errors(&self) -> Option<Vec<FieldError>> {
+        self.inner.field_errors()
+    }
+
+    fn retry_after(&self) -> Option<std::time::Duration> {
+        self.inner.retry_after()
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.inner.is_retryable()
+    }
+
+    fn is_retryable_for(&self, kind: OperationKind) -> bool {
+        self.inner.is_retryable_for(kind)
+    }
+
+    fn extra_headers(&self) -> Vec<(String, String)> {
+        self.inner.extra_headers()
+    }
+
+    fn hints(&self) -> Option<ErrorHints> {
+        self.inner.hints()
+    }
+
+    fn variant_name(&self) -> String {
+        self.inner.variant_name()
+    }
+}
+
+// Collects the failures of an operation spanning several services instead of stopping at the
+// first one. Each error is pushed under a label (`user`, `template`, ...) which prefixes its
+// entries in `field_errors`. Rendered as ValidationFailed while every error is a client error,
+// a server error among them takes over the code so it does not get reported as the client's
+// fault.
+#[derive(Debug, Default)]
+pub struct AggregateError {
+    errors: Vec<(String, Box<dyn ResponseError + Send + Sync>)>,
+}
+
+impl AggregateError {
+    pub fn new() -> Self {
+        AggregateError::default()
+    }
+
+    pub fn push<E: ResponseError + Send + Sync + 'static>(&mut self, label: &str, err: E) {
+        self.errors.push((label.to_string(), Box::new(err)));
+    }
+
+    // keeps the value of `result` or records its error
+    pub fn check<T, E: ResponseError + Send + Sync + 'static>(
+        &mut self,
+        label: &str,
+        result: Result<T, E>,
+    ) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.push(label, err);
+                None
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    // Ok while nothing was collected
+    pub fn finish(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl std::fmt::Display for AggregateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} errors:", self.errors.len())?;
+        for (label, err) in self.errors.iter() {
+            write!(f, " [{}] {};", label, err)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AggregateError {}
+
+impl ResponseError for AggregateError {
+    fn error_code(&self) -> ErrorCode {
+        self.errors
+            .iter()
+            .map(|(_, err)| err.error_code())
+            .find(|code| code.status_code().is_server_error())
+            .unwrap_or(ErrorCode::ValidationFailed)
+    }
+
+    fn user_message(&self) -> String {
+        self.error_code().default_message().to_string()
+    }
+
+    fn technical_details(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+
+    fn field_errors(&self) -> Option<Vec<FieldError>> {
+        let mut field_errors = vec![];
+        for (label, err) in self.errors.iter() {
+            match err.field_errors() {
+                Some(errors) => field_errors.extend(errors.into_iter().map(|error| FieldError {
+                    field: format!("{}.{}", label, error.field),
+                    message: error.message,
+                })),
+                None => field_errors.push(FieldError {
+                    field: label.clone(),
+                    message: err.user_message(),
+                }),
+            }
+        }
+        Some(field_errors)
+    }
+}
+
+// Escape hatch for failures which do not fit a typed service error (third party calls etc.),
+// lets handlers use `?` on `anyhow::Result`. The client only ever sees a generic message, the
+// anyhow chain goes to the technical details.
+#[derive(thiserror::Error, Debug)]
+#[error("unexpected error: {0}")]
+pub struct UnexpectedError(pub anyhow::Error);
+
+impl From<anyhow::Error> for UnexpectedError {
+    fn from(err: anyhow::Error) -> Self {
+        UnexpectedError(err)
+    }
+}
+
+impl ResponseError for UnexpectedError {
+    fn error_code(&self) -> ErrorCode {
+        ErrorCode::InternalServerError
+    }
+
+    fn user_message(&self) -> String {
+        "An unexpected error occurred".to_string()
+    }
+
+    fn technical_details(&self) -> Option<String> {
+        Some(format!("{:#}", self.0))
+    }
+}
+
+// A downstream dependency (database, cache, third party api) failed. Transient failures are
+// reported as ServiceUnavailable so clients know to come back, anything else stays a 500.
+#[derive(thiserror::Error, Debug)]
+#[error("dependency `{dependency}` failed: {reason}")]
+pub struct DependencyError {
+    pub dependency: String,
+    pub reason: String,
+    pub transient: bool,
+}
+
+impl DependencyError {
+    pub fn unavailable(dependency: &str, reason: &str) -> Self {
+        DependencyError {
+            dependency: dependency.to_string(),
+            reason: reason.to_string(),
+            transient: true,
+        }
+    }
+
+    pub fn failed(dependency: &str, reason: &str) -> Self {
+        DependencyError {
+            dependency: dependency.to_string(),
+            reason: reason.to_string(),
+            transient: false,
+        }
+    }
+}
+
+impl ResponseError for DependencyError {
+    fn error_code(&self) -> ErrorCode {
+        if self.transient {
+            ErrorCode::ServiceUnavailable
+        } else {
+            ErrorCode::InternalServerError
+        }
+    }
+
+    fn user_message(&self) -> String {
+        if self.transient {
+            "Service is temporarily unavailable, please try again later".to_string()
+        } else {
+            "An unexpected error occurred".to_string()
+        }
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(format!("dependency `{}` is unavailable", self.dependency))
+    }
+
+    fn technical_details(&self) -> Option<String> {
+        Some(self.reason.clone())
+    }
+
+    // a write may have gone through before the dependency dropped, retrying it is not safe
+    fn error_code_for(&self, kind: OperationKind) -> ErrorCode {
+        match kind {
+            OperationKind::Read => self.error_code(),
+            OperationKind::Write => ErrorCode::InternalServerError,
+        }
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.transient
+    }
+
+    fn is_retryable_for(&self, kind: OperationKind) -> bool {
+        kind == OperationKind::Read && self.transient
+    }
+}
+
+// JSON parsed by hand inside a service (embedded documents etc.) which turned out malformed,
+// `?` on a `serde_json::Result` converts into it
+#[derive(thiserror::Error, Debug)]
+#[error("malformed json: {0}")]
+pub struct MalformedJson(#[from] pub serde_json::Error);
+
+impl ResponseError for MalformedJson {
+    fn error_code(&self) -> ErrorCode {
+        ErrorCode::BadRequest
+    }
+
+    fn user_message(&self) -> String {
+        "The JSON document is not valid".to_string()
+    }
+
+    // e.g. `invalid json at line 3, column 14`, points the client at the broken spot
+    fn technical_description(&self) -> Option<String> {
+        Some(format!(
+            "invalid json at line {}, column {}",
+            self.0.line(),
+            self.0.column()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("test error")]
+    struct TestError(ErrorCode);
+
+    impl ResponseError for TestError {
+        fn error_code(&self) -> ErrorCode {
+            self.0
+        }
+    }
+
+    #[test]
+    fn status_code_is_derived_from_error_code() {
+        let expected = [
+            (ErrorCode::NotFound, 404),
+            (ErrorCode::BadRequest, 400),
+            (ErrorCode::UnAuthorized, 401),
+            (ErrorCode::Forbidden, 403),
+            (ErrorCode::Conflict, 409),
+            (ErrorCode::MethodNotAllowed, 405),
+            (ErrorCode::PreconditionFailed, 412),
+            (ErrorCode::ValidationFailed, 422),
+            (ErrorCode::UnprocessableEntity, 422),
+            (ErrorCode::PayloadTooLarge, 413),
+            (ErrorCode::UnsupportedMediaType, 415),
+            (ErrorCode::TooManyRequests, 429),
+            (ErrorCode::InternalServerError, 500),
+            (ErrorCode::ServiceUnavailable, 503),
+            (ErrorCode::GatewayTimeout, 504),
+        ];
+        assert_eq!(expected.len(), ErrorCode::ALL.len());
+        for (code, status) in expected {
+            assert_eq!(code.status_code().as_u16(), status, "{:?}", code);
+            // errors implementing only `error_code` get the same status
+            assert_eq!(TestError(code).status_code().as_u16(), status, "{:?}", code);
+        }
+    }
+
+    // `Backtraced::new` captures only with `RUST_BACKTRACE` set. Without it the backtrace is
+    // disabled and nothing is appended to the details, built explicitly here because the test
+    // environment may have the variable set.
+    #[test]
+    fn backtrace_is_not_appended_when_disabled() {
+        let err = Backtraced {
+            inner: TestError(ErrorCode::InternalServerError),
+            backtrace: std::backtrace::Backtrace::disabled(),
+        };
+        assert_eq!(err.details_chain(), None);
+        assert_eq!(err.error_details(), None);
+    }
+
+    #[test]
+    fn captured_backtrace_is_appended_to_details() {
+        let err = Backtraced {
+            inner: TestError(ErrorCode::InternalServerError),
+            backtrace: std::backtrace::Backtrace::force_capture(),
+        };
+        let details = err.error_details().unwrap();
+        assert!(details.starts_with("backtrace:\n"), "{}", details);
+    }
+
+    // fields of every event emitted while it is the default subscriber
+    #[derive(Clone, Default)]
+    struct Capture(std::sync::Arc<std::sync::Mutex<Vec<CapturedEvent>>>);
+
+    struct CapturedEvent {
+        level: tracing::Level,
+        fields: std::collections::BTreeMap<String, String>,
+    }
+
+    struct FieldVisitor<'a>(&'a mut std::collections::BTreeMap<String, String>);
+
+    impl tracing::field::Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for Capture {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = std::collections::BTreeMap::new();
+            event.record(&mut FieldVisitor(&mut fields));
+            self.0.lock().unwrap().push(CapturedEvent {
+                level: *event.metadata().level(),
+                fields,
+            });
+        }
+    }
+
+    fn captured<F: FnOnce()>(f: F) -> Vec<CapturedEvent> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, f);
+        let events = std::mem::take(&mut *capture.0.lock().unwrap());
+        events
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("test error")]
+    struct DescribedError(ErrorCode);
+
+    impl ResponseError for DescribedError {
+        fn error_code(&self) -> ErrorCode {
+            self.0
+        }
+
+        fn technical_description(&self) -> Option<String> {
+            Some("what went wrong".to_string())
+        }
+
+        fn technical_details(&self) -> Option<String> {
+            Some("inner failure".to_string())
+        }
+    }
+
+    #[test]
+    fn every_error_emits_one_structured_event() {
+        let events = captured(|| {
+            ApiError::new(
+                &DescribedError(ErrorCode::InternalServerError),
+                "test.op",
+                "trace-1",
+            );
+        });
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.level, tracing::Level::ERROR);
+        assert_eq!(event.fields["trace_id"], "trace-1");
+        assert_eq!(event.fields["operation"], "test.op");
+        assert_eq!(event.fields["error_code"], "InternalServerError");
+        assert_eq!(event.fields["status"], "500");
+        assert_eq!(event.fields["description"], "what went wrong");
+    }
+
+    #[test]
+    fn client_errors_are_logged_below_error_level() {
+        for code in [
+            ErrorCode::NotFound,
+            ErrorCode::ValidationFailed,
+            ErrorCode::TooManyRequests,
+        ] {
+            let events = captured(|| {
+                ApiError::new(&DescribedError(code), "test.op", "trace-1");
+            });
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].level, tracing::Level::WARN, "{:?}", code);
+        }
+    }
+
+    // part of the api contract, changing one of these strings breaks clients
+    #[test]
+    fn error_codes_serialize_as_screaming_snake_case() {
+        let expected = [
+            (ErrorCode::NotFound, "NOT_FOUND"),
+            (ErrorCode::BadRequest, "BAD_REQUEST"),
+            (ErrorCode::UnAuthorized, "UNAUTHORIZED"),
+            (ErrorCode::Forbidden, "FORBIDDEN"),
+            (ErrorCode::Conflict, "CONFLICT"),
+            (ErrorCode::MethodNotAllowed, "METHOD_NOT_ALLOWED"),
+            (ErrorCode::PreconditionFailed, "PRECONDITION_FAILED"),
+            (ErrorCode::ValidationFailed, "VALIDATION_FAILED"),
+            (ErrorCode::UnprocessableEntity, "UNPROCESSABLE_ENTITY"),
+            (ErrorCode::PayloadTooLarge, "PAYLOAD_TOO_LARGE"),
+            (ErrorCode::UnsupportedMediaType, "UNSUPPORTED_MEDIA_TYPE"),
+            (ErrorCode::TooManyRequests, "TOO_MANY_REQUESTS"),
+            (ErrorCode::InternalServerError, "INTERNAL_SERVER_ERROR"),
+            (ErrorCode::ServiceUnavailable, "SERVICE_UNAVAILABLE"),
+            (ErrorCode::GatewayTimeout, "GATEWAY_TIMEOUT"),
+        ];
+        assert_eq!(expected.len(), ErrorCode::ALL.len());
+        for (code, string) in expected {
+            assert_eq!(serde_json::to_value(code).unwrap(), string, "{:?}", code);
+        }
+    }
+
+    // numeric ids are never reassigned, a failure here means an existing id was changed
+    #[test]
+    fn numeric_ids_are_stable() {
+        let expected = [
+            (ErrorCode::NotFound, 1001),
+            (ErrorCode::BadRequest, 1002),
+            (ErrorCode::UnAuthorized, 1003),
+            (ErrorCode::Forbidden, 1004),
+            (ErrorCode::Conflict, 1005),
+            (ErrorCode::ValidationFailed, 1006),
+            (ErrorCode::TooManyRequests, 1007),
+            (ErrorCode::InternalServerError, 1008),
+            (ErrorCode::ServiceUnavailable, 1009),
+            (ErrorCode::PayloadTooLarge, 1010),
+            (ErrorCode::UnprocessableEntity, 1011),
+            (ErrorCode::MethodNotAllowed, 1012),
+            (ErrorCode::PreconditionFailed, 1013),
+            (ErrorCode::GatewayTimeout, 1014),
+            (ErrorCode::UnsupportedMediaType, 1015),
+        ];
+        assert_eq!(expected.len(), ErrorCode::ALL.len());
+        for (code, id) in expected {
+            assert_eq!(code.numeric(), id, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn grpc_status_follows_google_rpc_code() {
+        let expected = [
+            (ErrorCode::NotFound, 5),
+            (ErrorCode::BadRequest, 3),
+            (ErrorCode::UnAuthorized, 16),
+            (ErrorCode::Forbidden, 7),
+            (ErrorCode::Conflict, 6),
+            (ErrorCode::MethodNotAllowed, 12),
+            (ErrorCode::PreconditionFailed, 9),
+            (ErrorCode::ValidationFailed, 3),
+            (ErrorCode::UnprocessableEntity, 9),
+            (ErrorCode::PayloadTooLarge, 3),
+            (ErrorCode::UnsupportedMediaType, 3),
+            (ErrorCode::TooManyRequests, 8),
+            (ErrorCode::InternalServerError, 13),
+            (ErrorCode::ServiceUnavailable, 14),
+            (ErrorCode::GatewayTimeout, 4),
+        ];
+        assert_eq!(expected.len(), ErrorCode::ALL.len());
+        for (code, grpc) in expected {
+            assert_eq!(code.grpc_status(), grpc, "{:?}", code);
+        }
+    }
+
+    // Exhaustive without a wildcard, so a new variant does not compile until it gets the next
+    // index here, which then fails the test below until it is added to `ErrorCode::ALL`
+    fn variant_index(code: ErrorCode) -> usize {
+        match code {
+            ErrorCode::NotFound => 0,
+            ErrorCode::BadRequest => 1,
+            ErrorCode::UnAuthorized => 2,
+            ErrorCode::Forbidden => 3,
+            ErrorCode::Conflict => 4,
+            ErrorCode::MethodNotAllowed => 5,
+            ErrorCode::PreconditionFailed => 6,
+            ErrorCode::ValidationFailed => 7,
+            ErrorCode::UnprocessableEntity => 8,
+            ErrorCode::PayloadTooLarge => 9,
+            ErrorCode::UnsupportedMediaType => 10,
+            ErrorCode::TooManyRequests => 11,
+            ErrorCode::InternalServerError => 12,
+            ErrorCode::ServiceUnavailable => 13,
+            ErrorCode::GatewayTimeout => 14,
+        }
+    }
+
+    const VARIANT_COUNT: usize = 15;
+
+    #[test]
+    fn all_lists_every_variant_once() {
+        assert_eq!(ErrorCode::ALL.len(), VARIANT_COUNT);
+        let mut seen = [false; VARIANT_COUNT];
+        for code in ErrorCode::ALL {
+            let index = variant_index(code);
+            assert!(!seen[index], "{:?} is listed twice", code);
+            seen[index] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen));
+    }
+
+    #[test]
+    fn registry_describes_every_variant() {
+        let registry = registry();
+        assert_eq!(registry.len(), ErrorCode::ALL.len());
+        for (info, code) in registry.iter().zip(ErrorCode::ALL) {
+            assert_eq!(info.code, code);
+            assert_eq!(info.code_id, code.numeric());
+            assert_eq!(info.status, code.status_code().as_u16());
+        }
+    }
+
+    fn located() -> (Result<(), Located<TestError>>, u32) {
+        fn fail() -> Result<(), TestError> {
+            Err(TestError(ErrorCode::InternalServerError))
+        }
+        fn run() -> Result<(), Located<TestError>> {
+            fail()?;
+            Ok(())
+        }
+        // `fail()?` is on the third line above
+        (run(), line!() - 4)
+    }
+
+    #[test]
+    fn question_mark_records_its_own_line() {
+        let (result, line) = located();
+        let err = result.unwrap_err();
+        assert_eq!(err.location.file(), file!());
+        assert_eq!(err.location.line(), line);
+        assert_eq!(err.error_code(), ErrorCode::InternalServerError);
+    }
+
+    #[cfg(feature = "debug_errors")]
+    #[test]
+    fn location_is_added_to_the_details() {
+        let (result, line) = located();
+        let expected = format!("created at {}:{}", file!(), line);
+        assert_eq!(result.unwrap_err().details_chain(), Some(vec![expected]));
+    }
+
+    #[cfg(not(feature = "debug_errors"))]
+    #[test]
+    fn location_is_not_added_to_the_details() {
+        let (result, _) = located();
+        let err = result.unwrap_err();
+        assert_eq!(err.details_chain(), None);
+        assert_eq!(err.error_details(), None);
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("throttled")]
+    struct Throttled;
+
+    impl ResponseError for Throttled {
+        fn error_code(&self) -> ErrorCode {
+            ErrorCode::TooManyRequests
+        }
+
+        fn retry_after(&self) -> Option<std::time::Duration> {
+            Some(std::time::Duration::from_millis(2500))
+        }
+
+        fn extra_headers(&self) -> Vec<(String, String)> {
+            vec![("x-ratelimit-remaining".to_string(), "0".to_string())]
+        }
+    }
+
+    #[test]
+    fn problem_details_carry_the_error_headers() {
+        let problem = response_problem(&Throttled, "test.op", "trace-1");
+        assert_eq!(problem.status(), axum::http::StatusCode::TOO_MANY_REQUESTS);
+        let headers = problem.headers();
+        assert_eq!(
+            headers[axum::http::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        assert_eq!(headers[axum::http::header::RETRY_AFTER], "3");
+        assert_eq!(headers["x-ratelimit-remaining"], "0");
+        // the same headers as the envelope
+        let envelope = response(&Throttled, "test.op", "trace-1");
+        assert_eq!(envelope.headers()[axum::http::header::RETRY_AFTER], "3");
+        assert_eq!(envelope.headers()["x-ratelimit-remaining"], "0");
+    }
+
+    #[test]
+    fn unauthorized_problem_details_carry_a_challenge() {
+        let response = response_problem(&TestError(ErrorCode::UnAuthorized), "test.op", "trace-1");
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers()[axum::http::header::WWW_AUTHENTICATE],
+            WWW_AUTHENTICATE_CHALLENGE
+        );
+    }
+
+    fn support_url(base: &str, trace_id: &str) -> serde_json::Value {
+        let mut err = ApiError::new(&TestError(ErrorCode::NotFound), "test.op", trace_id);
+        SupportUrl(base.to_string()).enrich(&mut err);
+        err.extensions["support_url"].clone()
+    }
+
+    #[test]
+    fn support_url_appends_the_trace_id() {
+        assert_eq!(
+            support_url("https://support.example.com/ticket", "trace-1"),
+            "https://support.example.com/ticket?trace_id=trace-1"
+        );
+        assert_eq!(
+            support_url("https://support.example.com/ticket?lang=en", "trace-1"),
+            "https://support.example.com/ticket?lang=en&trace_id=trace-1"
+        );
+    }
+
+    #[test]
+    fn support_url_encodes_the_trace_id() {
+        assert_eq!(
+            support_url("https://support.example.com/ticket", "x&foo=bar #1"),
+            "https://support.example.com/ticket?trace_id=x%26foo%3Dbar+%231"
+        );
+    }
+
+    async fn body_for(kind: OperationKind) -> (axum::http::StatusCode, serde_json::Value) {
+        let err = DependencyError::unavailable("db", "connection reset");
+        let response = response_for(&err, kind, "test.op", "trace-1");
+        let status = response.status();
+        (status, crate::router::tests::body_json(response).await)
+    }
+
+    #[tokio::test]
+    async fn dependency_error_is_retryable_on_read_only() {
+        let (status, body) = body_for(OperationKind::Read).await;
+        assert_eq!(status, axum::http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"]["code"], "SERVICE_UNAVAILABLE");
+        assert_eq!(body["error"]["hints"]["retryable"], true);
+
+        let (status, body) = body_for(OperationKind::Write).await;
+        assert_eq!(status, axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["code"], "INTERNAL_SERVER_ERROR");
+        assert_eq!(body["error"]["hints"]["retryable"], false);
+    }
+
+    #[test]
+    fn jitter_stays_within_the_window() {
+        let mut state = 42u64;
+        let mut seen = std::collections::BTreeSet::new();
+        for _ in 0..1000 {
+            let value = jitter(10, 5, state);
+            assert!((10..=15).contains(&value), "{}", value);
+            seen.insert(value);
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        }
+        // spread over the whole window, not stuck on one value
+        assert_eq!(seen.len(), 6);
+    }
+
+    #[test]
+    fn jitter_is_reproducible_from_the_seed() {
+        assert_eq!(jitter(10, 5, 42), jitter(10, 5, 42));
+        assert_eq!(jitter(10, 0, 42), 10);
+        assert_eq!(jitter(u64::MAX, 5, 42), u64::MAX);
+        // the largest window does not overflow `window + 1`
+        jitter(0, u64::MAX, 42);
+    }
+
+    // error with a description and details, at each verbosity
+    fn at(verbosity: Verbosity) -> serde_json::Value {
+        let err = DescribedError(ErrorCode::Conflict);
+        let api_error = ApiError::with_verbosity(&err, verbosity, "test.op", "trace-1");
+        serde_json::to_value(api_error).unwrap()
+    }
+
+    #[test]
+    fn public_verbosity_sends_the_message_only() {
+        let body = at(Verbosity::Public);
+        assert!(body["message"].is_string());
+        assert!(body.get("description").is_none());
+        assert!(body.get("details").is_none());
+    }
+
+    #[test]
+    fn description_verbosity_adds_the_description() {
+        let body = at(Verbosity::Description);
+        assert!(body["message"].is_string());
+        assert_eq!(body["description"], "what went wrong");
+        assert!(body.get("details").is_none());
+    }
+
+    #[test]
+    fn full_verbosity_adds_the_details() {
+        let body = at(Verbosity::Full);
+        assert!(body["message"].is_string());
+        assert_eq!(body["description"], "what went wrong");
+        assert_eq!(body["details"], "inner failure");
+    }
+
+    #[test]
+    fn details_are_never_sent_below_full_verbosity() {
+        assert_eq!(Verbosity::default(), Verbosity::Description);
+        for verbosity in [Verbosity::Public, Verbosity::Description] {
+            for code in ErrorCode::ALL {
+                let events = captured(|| {
+                    let api_error =
+                        ApiError::with_verbosity(&DescribedError(code), verbosity, "op", "t");
+                    let body = serde_json::to_value(api_error).unwrap();
+                    assert!(body.get("details").is_none(), "{:?} {:?}", verbosity, code);
+                    assert!(body.get("details_chain").is_none());
+                });
+                // only kept out of the body, the log still has them
+                assert_eq!(events[0].fields["details"], "inner failure");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn problem_details_use_the_rfc_7807_field_names() {
+        let response = response_problem(&TestError(ErrorCode::NotFound), "test.op", "trace-1");
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "NOT_FOUND",
+                "status": 404,
+                "detail": "test error",
+                "instance": "trace-1",
+            })
+        );
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("invalid user")]
+    struct InvalidUser;
+
+    impl ResponseError for InvalidUser {
+        fn error_code(&self) -> ErrorCode {
+            ErrorCode::ValidationFailed
+        }
+
+        fn field_errors(&self) -> Option<Vec<FieldError>> {
+            Some(vec![
+                FieldError::new("email", "must be a valid email address"),
+                FieldError::new("name", "must not be empty"),
+            ])
+        }
+    }
+
+    #[test]
+    fn field_errors_are_serialized_per_field() {
+        let body = serde_json::to_value(ApiError::new(&InvalidUser, "user.create", "t")).unwrap();
+        assert_eq!(body["code"], "VALIDATION_FAILED");
+        assert_eq!(
+            body["field_errors"],
+            serde_json::json!([
+                { "field": "email", "message": "must be a valid email address" },
+                { "field": "name", "message": "must not be empty" },
+            ])
+        );
+
+        // left out rather than `null` for errors without any
+        let body = serde_json::to_value(ApiError::new(&TestError(ErrorCode::NotFound), "op", "t"))
+            .unwrap();
+        assert!(body.get("field_errors").is_none());
+    }
+
+    fn third_party_call() -> Result<(), UnexpectedError> {
+        Err(anyhow::anyhow!("boom"))?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn anyhow_error_does_not_leak_at_public_verbosity() {
+        let err = third_party_call().unwrap_err();
+        let response =
+            ApiError::with_verbosity(&err, Verbosity::Public, "test.op", "trace-1").into_response();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = std::str::from_utf8(&bytes).unwrap();
+        assert!(!body.contains("boom"), "{}", body);
+        let body: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(body["error"]["code"], "INTERNAL_SERVER_ERROR");
+        assert_eq!(body["error"]["message"], "An unexpected error occurred");
+    }
+
+    #[tokio::test]
+    async fn body_status_matches_the_http_status() {
+        for code in ErrorCode::ALL {
+            let response = response(&TestError(code), "test.op", "trace-1");
+            let status = response.status().as_u16();
+            let body = crate::router::tests::body_json(response).await;
+            assert_eq!(body["error"]["status"], status, "{:?}", code);
+        }
+
+        // also when `error_code_for` overrides the code
+        let err = DependencyError::unavailable("db", "connection reset");
+        let response = response_for(&err, OperationKind::Write, "test.op", "trace-1");
+        let status = response.status().as_u16();
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["status"], status);
+    }
+
+    #[test]
+    fn timestamp_is_serialized_in_the_requested_format() {
+        use chrono::TimeZone;
+
+        let at = chrono::Utc
+            .with_ymd_and_hms(2024, 1, 1, 10, 0, 0)
+            .single()
+            .unwrap();
+        let rfc3339 =
+            timestamp::serialize_as(&at, TimestampFormat::Rfc3339, serde_json::value::Serializer)
+                .unwrap();
+        assert_eq!(rfc3339, serde_json::json!("2024-01-01T10:00:00.000Z"));
+        let millis = timestamp::serialize_as(
+            &at,
+            TimestampFormat::EpochMillis,
+            serde_json::value::Serializer,
+        )
+        .unwrap();
+        assert_eq!(millis, serde_json::json!(1704103200000_i64));
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("quota exhausted")]
+    struct DocumentedError;
+
+    impl ResponseError for DocumentedError {
+        fn error_code(&self) -> ErrorCode {
+            ErrorCode::TooManyRequests
+        }
+
+        fn hints(&self) -> Option<ErrorHints> {
+            Some(ErrorHints {
+                retryable: true,
+                documentation_url: Some("https://docs.example/errors/quota".to_string()),
+            })
+        }
+    }
+
+    fn hints_json<E: ResponseError>(err: &E) -> serde_json::Value {
+        let error = ApiError::with_verbosity(err, Verbosity::Public, "test.op", "trace-1");
+        serde_json::to_value(error).unwrap()["hints"].clone()
+    }
+
+    #[test]
+    fn hints_are_sent_with_the_retryable_flag_of_the_code() {
+        assert_eq!(
+            hints_json(&TestError(ErrorCode::ServiceUnavailable)),
+            serde_json::json!({ "retryable": true })
+        );
+        assert_eq!(
+            hints_json(&TestError(ErrorCode::TooManyRequests)),
+            serde_json::json!({ "retryable": true })
+        );
+        assert_eq!(
+            hints_json(&TestError(ErrorCode::NotFound)),
+            serde_json::json!({ "retryable": false })
+        );
+    }
+
+    #[test]
+    fn hints_carry_the_documentation_url_when_the_error_has_one() {
+        let expected = serde_json::json!({
+            "retryable": true,
+            "documentation_url": "https://docs.example/errors/quota",
+        });
+        assert_eq!(hints_json(&DocumentedError), expected);
+        // wrappers pass the hints of the inner error on
+        let located = Located {
+            inner: DocumentedError,
+            location: std::panic::Location::caller(),
+        };
+        assert_eq!(hints_json(&located), expected);
+    }
+
+    fn find(code: ErrorCode) -> Result<(), TestError> {
+        Err(TestError(code))
+    }
+
+    async fn question_mark() -> Result<axum::response::Response, AppError> {
+        find(ErrorCode::NotFound)?;
+        Ok(crate::response::success("unreachable"))
+    }
+
+    async fn with_operation() -> Result<axum::response::Response, AppError> {
+        find(ErrorCode::Conflict).map_err(|err| AppError::new(err, "item.update"))?;
+        Ok(crate::response::success("unreachable"))
+    }
+
+    #[tokio::test]
+    async fn app_error_renders_the_status_and_operation_of_the_error() {
+        let router = axum::Router::new()
+            .route("/find", axum::routing::get(question_mark))
+            .route("/update", axum::routing::get(with_operation));
+        for (uri, status, code, operation) in [
+            ("/find", 404, "NOT_FOUND", "request"),
+            ("/update", 409, "CONFLICT", "item.update"),
+        ] {
+            let request = axum::http::Request::get(uri)
+                .header("x-request-id", "req-56")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            let response = crate::router::tests::send_through(router.clone(), request).await;
+            assert_eq!(response.status().as_u16(), status, "{}", uri);
+            let body = crate::router::tests::body_json(response).await;
+            assert_eq!(body["success"], false);
+            assert_eq!(body["error"]["code"], code);
+            assert_eq!(body["error"]["operation"], operation);
+            assert_eq!(body["error"]["message"], "test error");
+            assert_eq!(body["error"]["trace_id"], "req-56");
+        }
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("pool exhausted")]
+    struct LeakyError;
+
+    impl ResponseError for LeakyError {
+        fn error_code(&self) -> ErrorCode {
+            ErrorCode::InternalServerError
+        }
+
+        fn user_message(&self) -> String {
+            "database pool db-1 exhausted".to_string()
+        }
+    }
+
+    #[test]
+    fn server_error_message_is_masked_but_logged() {
+        let mut errors = vec![];
+        let events = captured(|| {
+            errors.push(ApiError::with_verbosity(
+                &LeakyError,
+                Verbosity::Public,
+                "test.op",
+                "trace-1",
+            ));
+        });
+        // masking is on unless the settings turn it off
+        assert_eq!(errors[0].message, "An unexpected error occurred");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].fields["message"], "database pool db-1 exhausted");
+    }
+
+    #[test]
+    fn client_message_is_masked_only_for_server_errors_when_enabled() {
+        let message = || "database pool db-1 exhausted".to_string();
+        let masked = client_message(
+            ErrorCode::InternalServerError,
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            message(),
+            true,
+        );
+        assert_eq!(masked, ErrorCode::InternalServerError.default_message());
+        let unmasked = client_message(
+            ErrorCode::InternalServerError,
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+            message(),
+            false,
+        );
+        assert_eq!(unmasked, message());
+        let client_error = client_message(
+            ErrorCode::Conflict,
+            axum::http::StatusCode::CONFLICT,
+            message(),
+            true,
+        );
+        assert_eq!(client_error, message());
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("connection refused")]
+    struct IoFailure;
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("query failed")]
+    struct QueryFailure(#[source] IoFailure);
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("loading the user failed")]
+    struct LoadFailure(#[source] QueryFailure);
+
+    impl ResponseError for LoadFailure {
+        fn error_code(&self) -> ErrorCode {
+            ErrorCode::InternalServerError
+        }
+
+        fn technical_details(&self) -> Option<String> {
+            Some("users.get".to_string())
+        }
+    }
+
+    #[test]
+    fn details_chain_walks_every_source() {
+        let err = LoadFailure(QueryFailure(IoFailure));
+        assert_eq!(
+            err.details_chain(),
+            Some(vec![
+                "users.get".to_string(),
+                "query failed".to_string(),
+                "connection refused".to_string(),
+            ])
+        );
+        assert_eq!(
+            err.error_details().as_deref(),
+            Some("users.get\nquery failed\nconnection refused")
+        );
+    }
+
+    fn parse(document: &str) -> Result<serde_json::Value, MalformedJson> {
+        Ok(serde_json::from_str(document)?)
+    }
+
+    #[test]
+    fn malformed_json_points_at_the_line_and_column() {
+        let err = parse("{\n  \"name\": \"a\",\n  \"tags\": [1,, 2]\n}").unwrap_err();
+        assert_eq!(err.status_code(), axum::http::StatusCode::BAD_REQUEST);
+        let error = ApiError::with_verbosity(&err, Verbosity::Description, "test.op", "trace-1");
+        assert_eq!(error.message, "The JSON document is not valid");
+        assert_eq!(
+            error.description.as_deref(),
+            Some("invalid json at line 3, column 14")
+        );
+    }
+
+    async fn in_context(verbosity: Verbosity) -> serde_json::Value {
+        let context = ErrorContext {
+            user_id: Some("user-1".to_string()),
+            tenant_id: Some("tenant-1".to_string()),
+        };
+        let error = scope_context(context, async {
+            ApiError::with_verbosity(&TestError(ErrorCode::Conflict), verbosity, "test.op", "t")
+        })
+        .await;
+        serde_json::to_value(error).unwrap()
+    }
+
+    #[tokio::test]
+    async fn error_context_reaches_the_envelope() {
+        let body = in_context(Verbosity::Description).await;
+        assert_eq!(body["user_id"], "user-1");
+        assert_eq!(body["tenant_id"], "tenant-1");
+    }
+
+    #[tokio::test]
+    async fn error_context_is_not_sent_at_public_verbosity() {
+        let body = in_context(Verbosity::Public).await;
+        assert!(body.get("user_id").is_none());
+        assert!(body.get("tenant_id").is_none());
+    }
+
+    fn aggregate() -> AggregateError {
+        let mut errors = AggregateError::new();
+        errors.push(
+            "user",
+            crate::controller::validate::ValidationError(vec![
+                FieldError::new("email", "must be an email"),
+                FieldError::new("name", "must not be empty"),
+            ]),
+        );
+        let template: Result<(), TestError> = Err(TestError(ErrorCode::Conflict));
+        assert!(errors.check("template", template).is_none());
+        errors
+    }
+
+    #[test]
+    fn aggregate_lists_every_inner_error() {
+        let errors = aggregate();
+        assert_eq!(errors.error_code(), ErrorCode::ValidationFailed);
+        assert_eq!(
+            errors.field_errors(),
+            Some(vec![
+                FieldError::new("user.email", "must be an email"),
+                FieldError::new("user.name", "must not be empty"),
+                FieldError::new("template", "test error"),
+            ])
+        );
+        assert!(AggregateError::new().finish().is_ok());
+    }
+
+    #[test]
+    fn server_error_in_aggregate_takes_over_the_code() {
+        let mut errors = aggregate();
+        errors.push("cache", DependencyError::unavailable("redis", "timeout"));
+        assert_eq!(errors.error_code(), ErrorCode::ServiceUnavailable);
+        assert_eq!(errors.field_errors().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn variant_is_sent_only_at_full_verbosity() {
+        let err = crate::middleware::auth::AuthError::MissingToken;
+        let full = ApiError::with_verbosity(&err, Verbosity::Full, "test.op", "trace-1");
+        assert_eq!(full.variant.as_deref(), Some("MissingToken"));
+        let public = ApiError::with_verbosity(&err, Verbosity::Public, "test.op", "trace-1");
+        assert_eq!(public.variant, None);
+        assert!(serde_json::to_value(public)
+            .unwrap()
+            .get("variant")
+            .is_none());
+    }
+
+    #[test]
+    fn retryable_per_code() {
+        let expected = [
+            (ErrorCode::NotFound, false),
+            (ErrorCode::BadRequest, false),
+            (ErrorCode::UnAuthorized, false),
+            (ErrorCode::Forbidden, false),
+            (ErrorCode::Conflict, false),
+            (ErrorCode::MethodNotAllowed, false),
+            (ErrorCode::PreconditionFailed, false),
+            (ErrorCode::ValidationFailed, false),
+            (ErrorCode::UnprocessableEntity, false),
+            (ErrorCode::PayloadTooLarge, false),
+            (ErrorCode::UnsupportedMediaType, false),
+            (ErrorCode::TooManyRequests, true),
+            (ErrorCode::InternalServerError, true),
+            (ErrorCode::ServiceUnavailable, true),
+            (ErrorCode::GatewayTimeout, true),
+        ];
+        assert_eq!(expected.len(), ErrorCode::ALL.len());
+        for (code, retryable) in expected {
+            assert_eq!(code.is_retryable(), retryable, "{:?}", code);
+            assert_eq!(TestError(code).is_retryable(), retryable, "{:?}", code);
+        }
+    }
+
+    // rejected input which only becomes unprocessable once a write tries to apply it, both
+    // codes share the 422 status
+    #[derive(thiserror::Error, Debug)]
+    #[error("invalid transition")]
+    struct InvalidTransition;
+
+    impl ResponseError for InvalidTransition {
+        fn error_code(&self) -> ErrorCode {
+            ErrorCode::ValidationFailed
+        }
+
+        fn error_code_for(&self, kind: OperationKind) -> ErrorCode {
+            match kind {
+                OperationKind::Read => ErrorCode::ValidationFailed,
+                OperationKind::Write => ErrorCode::UnprocessableEntity,
+            }
+        }
+    }
+
+    #[test]
+    fn code_comes_from_the_operation_kind_not_the_status() {
+        let write = ApiError::with_kind(
+            &InvalidTransition,
+            Some(OperationKind::Write),
+            Verbosity::Public,
+            "test.op",
+            "trace-1",
+        );
+        assert_eq!(write.code, ErrorCode::UnprocessableEntity);
+        assert_eq!(write.status, axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+        let read = ApiError::with_kind(
+            &InvalidTransition,
+            Some(OperationKind::Read),
+            Verbosity::Public,
+            "test.op",
+            "trace-1",
+        );
+        assert_eq!(read.code, ErrorCode::ValidationFailed);
+        assert_eq!(read.status, axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+    }
+}
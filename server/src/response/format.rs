@@ -0,0 +1,197 @@
+use axum::response::IntoResponse;
+
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+pub const PRETTY_HEADER: &str = "x-pretty";
+pub const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+// Body format negotiated from the `Accept` header, JSON unless the client asks for MessagePack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Json,
+    // indented JSON for debugging, asked for with `?pretty=true` or `X-Pretty: true`
+    PrettyJson,
+    MsgPack,
+    // `Accept: text/plain` without json, monitoring clients etc. Errors are sent as their plain
+    // message, success bodies have no text form and stay JSON.
+    Text,
+}
+
+tokio::task_local! {
+    static FORMAT: Format;
+}
+
+impl Format {
+    pub fn from_headers(headers: &axum::http::HeaderMap) -> Self {
+        let accept: Vec<&str> = headers
+            .get_all(axum::http::header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        let accepts = |content_type: &str| accept.iter().any(|value| value.contains(content_type));
+        if accepts(MSGPACK_CONTENT_TYPE) {
+            Format::MsgPack
+        } else if accepts("text/plain") && !accepts("application/json") {
+            Format::Text
+        } else {
+            Format::Json
+        }
+    }
+
+    // like `from_headers`, but also honours the pretty flag of JSON responses
+    pub fn from_request(uri: &axum::http::Uri, headers: &axum::http::HeaderMap) -> Self {
+        let format = Format::from_headers(headers);
+        if format != Format::Json {
+            return format;
+        }
+        let pretty_query = uri
+            .query()
+            .is_some_and(|query| query.split('&').any(|pair| pair == "pretty=true"));
+        let pretty_header = headers
+            .get(PRETTY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+        if pretty_query || pretty_header {
+            Format::PrettyJson
+        } else {
+            Format::Json
+        }
+    }
+
+    // format of the request being handled, set by the `negotiate` middleware
+    pub fn current() -> Self {
+        FORMAT.try_with(|format| *format).unwrap_or_default()
+    }
+}
+
+pub async fn scope<F: std::future::Future>(format: Format, f: F) -> F::Output {
+    FORMAT.scope(format, f).await
+}
+
+// Body bytes and content type in the negotiated format, for helpers which need the exact bytes
+// sent (digests etc.)
+pub fn serialize<T: serde::Serialize>(
+    body: &T,
+) -> Result<(Vec<u8>, &'static str), Box<dyn std::error::Error + Send + Sync>> {
+    Ok(match Format::current() {
+        Format::Json | Format::Text => (serde_json::to_vec(body)?, "application/json"),
+        Format::PrettyJson => (serde_json::to_vec_pretty(body)?, "application/json"),
+        Format::MsgPack => (rmp_serde::to_vec_named(body)?, MSGPACK_CONTENT_TYPE),
+    })
+}
+
+// Single place where success and error bodies get serialized
+pub fn render<T: serde::Serialize>(
+    status: axum::http::StatusCode,
+    body: &T,
+) -> axum::response::Response {
+    match serialize(body) {
+        Ok((bytes, content_type)) => (
+            status,
+            [(axum::http::header::CONTENT_TYPE, content_type)],
+            bytes,
+        )
+            .into_response(),
+        Err(err) => {
+            tracing::error!("failed to serialize response: {}", err);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn request(uri: &str, accept: &str) -> axum::http::Request<axum::body::Body> {
+        axum::http::Request::get(uri)
+            .header(axum::http::header::ACCEPT, accept)
+            .body(axum::body::Body::empty())
+            .unwrap()
+    }
+
+    fn router() -> axum::Router {
+        axum::Router::new().route(
+            "/item",
+            axum::routing::get(|| async {
+                crate::response::success(serde_json::json!({ "id": 1 }))
+            }),
+        )
+    }
+
+    async fn msgpack_body(response: axum::response::Response) -> serde_json::Value {
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            super::MSGPACK_CONTENT_TYPE
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        rmp_serde::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn success_is_sent_as_msgpack_when_accepted() {
+        let response =
+            crate::router::tests::send_through(router(), request("/item", "application/msgpack"))
+                .await;
+        let body = msgpack_body(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn error_is_sent_as_msgpack_when_accepted() {
+        let response =
+            crate::router::tests::send_default(request("/missing", "application/msgpack")).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        let body = msgpack_body(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn json_is_the_default() {
+        let response = crate::router::tests::send_through(router(), request("/item", "*/*")).await;
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"]["id"], 1);
+    }
+
+    async fn text_body(response: axum::response::Response) -> String {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn error_is_sent_as_plain_text_when_accepted() {
+        let response =
+            crate::router::tests::send_default(request("/no-such-route", "text/plain")).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            super::TEXT_CONTENT_TYPE
+        );
+        let body = text_body(response).await;
+        assert!(body.starts_with("Route not found\n"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn success_stays_json_when_text_is_accepted() {
+        assert_eq!(
+            super::Format::from_headers(request("/item", "text/plain").headers()),
+            super::Format::Text
+        );
+        let response =
+            crate::router::tests::send_through(router(), request("/item", "text/plain")).await;
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"]["id"], 1);
+    }
+}
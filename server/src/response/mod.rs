@@ -0,0 +1,771 @@
+pub mod error;
+pub mod format;
+
+use axum::response::IntoResponse;
+
+#[derive(Debug, serde::Serialize)]
+pub struct ApiSuccess<T, M = ()> {
+    pub success: bool,
+    pub data: T,
+    // pagination info, timings etc. which do not belong inside `data`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<M>,
+    // same id as the `trace_id` of error bodies, only absent outside of the request id middleware
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+    // caveats of a request which still succeeded, e.g. a deprecated field was used
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+fn current_trace_id() -> Option<String> {
+    crate::middleware::request_id::RequestId::current().map(|request_id| request_id.0)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HeaderError {
+    #[error("invalid header name `{name}`: {source}")]
+    InvalidName {
+        name: String,
+        source: axum::http::header::InvalidHeaderName,
+    },
+    #[error("invalid value for header `{name}`: {source}")]
+    InvalidValue {
+        name: String,
+        source: axum::http::header::InvalidHeaderValue,
+    },
+}
+
+impl error::ResponseError for HeaderError {
+    fn error_code(&self) -> error::ErrorCode {
+        error::ErrorCode::InternalServerError
+    }
+}
+
+// `success: true` under a 4xx/5xx status contradicts itself, caught before anything is sent
+#[derive(thiserror::Error, Debug)]
+#[error("success response built with non 2xx status {0}")]
+pub struct NotSuccessStatus(pub axum::http::StatusCode);
+
+impl error::ResponseError for NotSuccessStatus {
+    fn error_code(&self) -> error::ErrorCode {
+        error::ErrorCode::InternalServerError
+    }
+}
+
+pub fn success<T: serde::Serialize>(data: T) -> axum::response::Response {
+    success_with_status(data, axum::http::StatusCode::OK)
+}
+
+pub fn success_with_status<T: serde::Serialize>(
+    data: T,
+    status: axum::http::StatusCode,
+) -> axum::response::Response {
+    if !status.is_success() {
+        return error::response(
+            &NotSuccessStatus(status),
+            "response.success",
+            error::trace_id().as_str(),
+        );
+    }
+    format::render(
+        status,
+        &ApiSuccess::<T> {
+            success: true,
+            data,
+            meta: None,
+            trace_id: current_trace_id(),
+            warnings: vec![],
+        },
+    )
+}
+
+pub fn success_with_meta<T: serde::Serialize, M: serde::Serialize>(
+    data: T,
+    meta: M,
+) -> axum::response::Response {
+    format::render(
+        axum::http::StatusCode::OK,
+        &ApiSuccess {
+            success: true,
+            data,
+            meta: Some(meta),
+            trace_id: current_trace_id(),
+            warnings: vec![],
+        },
+    )
+}
+
+pub fn success_with_warnings<T: serde::Serialize>(
+    data: T,
+    warnings: Vec<String>,
+) -> axum::response::Response {
+    ResponseBuilder::new(data).warnings(warnings).build()
+}
+
+// 201 with the `Location` of the newly created resource, e.g. `/templates/1`
+pub fn created<T: serde::Serialize>(data: T, location: &str) -> axum::response::Response {
+    let mut response = success_with_status(data, axum::http::StatusCode::CREATED);
+    match insert_header(
+        response.headers_mut(),
+        axum::http::header::LOCATION.as_str(),
+        location,
+    ) {
+        Ok(()) => response,
+        Err(err) => error::response(&err, "response.created", error::trace_id().as_str()),
+    }
+}
+
+// Strong ETag over the serialized data, already quoted as the header expects. sha-256 so the
+// tag stays the same across builds and every instance agrees on it.
+pub fn etag<T: serde::Serialize>(data: &T) -> String {
+    use sha2::Digest;
+
+    let digest = sha2::Sha256::digest(serde_json::to_vec(data).unwrap_or_default());
+    format!("\"{:x}\"", digest)
+}
+
+// true when the request's `If-None-Match` already holds `etag`, i.e. a 304 can be sent
+pub fn etag_matches(headers: &axum::http::HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(axum::http::header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("If-Match does not hold the current version {current}")]
+pub struct StaleVersion {
+    pub current: String,
+}
+
+impl error::ResponseError for StaleVersion {
+    fn error_code(&self) -> error::ErrorCode {
+        error::ErrorCode::PreconditionFailed
+    }
+
+    fn user_message(&self) -> String {
+        "The resource was modified since you last fetched it, fetch it again and retry".to_string()
+    }
+
+    fn technical_description(&self) -> Option<String> {
+        Some(self.to_string())
+    }
+}
+
+// Optimistic concurrency for updates: passes when the request has no `If-Match` or one of its
+// tags is the current `etag` (or `*`). Weak tags never match, If-Match compares strongly.
+pub fn check_if_match(headers: &axum::http::HeaderMap, etag: &str) -> Result<(), StaleVersion> {
+    let mut tags = headers
+        .get_all(axum::http::header::IF_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .peekable();
+    if tags.peek().is_none() || tags.any(|tag| tag == "*" || tag == etag) {
+        Ok(())
+    } else {
+        Err(StaleVersion {
+            current: etag.to_string(),
+        })
+    }
+}
+
+pub fn success_with_etag<T: serde::Serialize>(data: T, etag: &str) -> axum::response::Response {
+    let mut response = success(data);
+    match insert_header(
+        response.headers_mut(),
+        axum::http::header::ETAG.as_str(),
+        etag,
+    ) {
+        Ok(()) => response,
+        Err(err) => error::response(&err, "response.etag", error::trace_id().as_str()),
+    }
+}
+
+pub fn not_modified(etag: &str) -> axum::response::Response {
+    let mut response = axum::http::StatusCode::NOT_MODIFIED.into_response();
+    match insert_header(
+        response.headers_mut(),
+        axum::http::header::ETAG.as_str(),
+        etag,
+    ) {
+        Ok(()) => response,
+        Err(err) => error::response(&err, "response.etag", error::trace_id().as_str()),
+    }
+}
+
+pub const DIGEST_HEADER: &str = "digest";
+
+// 200 with a `Digest: sha-256=<base64>` header over the exact body bytes, lets clients verify
+// large payloads
+pub fn success_with_digest<T: serde::Serialize>(data: T) -> axum::response::Response {
+    use base64::Engine;
+    use sha2::Digest;
+
+    let body = ApiSuccess::<T> {
+        success: true,
+        data,
+        meta: None,
+        trace_id: current_trace_id(),
+        warnings: vec![],
+    };
+    let (bytes, content_type) = match format::serialize(&body) {
+        Ok(serialized) => serialized,
+        // same fallback as `render`
+        Err(_) => return format::render(axum::http::StatusCode::OK, &body),
+    };
+    let digest = format!(
+        "sha-256={}",
+        base64::engine::general_purpose::STANDARD.encode(sha2::Sha256::digest(&bytes))
+    );
+    (
+        [
+            (axum::http::header::CONTENT_TYPE.as_str(), content_type),
+            (DIGEST_HEADER, digest.as_str()),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+// 200 which shared caches may keep for `max_age`, whole seconds rounded down
+pub fn success_cacheable<T: serde::Serialize>(
+    data: T,
+    max_age: std::time::Duration,
+) -> axum::response::Response {
+    ResponseBuilder::new(data)
+        .header(
+            axum::http::header::CACHE_CONTROL.as_str(),
+            format!("public, max-age={}", max_age.as_secs()).as_str(),
+        )
+        .build()
+}
+
+// one page of a list endpoint, with the pages around it in the `Link` header as well
+pub fn success_paginated<T: serde::Serialize>(
+    page: crate::controller::types::Paginated<T>,
+    uri: &axum::http::Uri,
+) -> axum::response::Response {
+    let links = page.links(uri);
+    ResponseBuilder::new(page)
+        .header(axum::http::header::LINK.as_str(), links.as_str())
+        .build()
+}
+
+// 202 for operations which were queued instead of completed
+pub fn accepted<T: serde::Serialize>(data: T) -> axum::response::Response {
+    success_with_status(data, axum::http::StatusCode::ACCEPTED)
+}
+
+// 202 with the `Location` of the url which can be polled for the status of the operation
+pub fn accepted_at<T: serde::Serialize>(data: T, location: &str) -> axum::response::Response {
+    ResponseBuilder::new(data)
+        .status(axum::http::StatusCode::ACCEPTED)
+        .header(axum::http::header::LOCATION.as_str(), location)
+        .build()
+}
+
+// 204 with an empty body, used by the delete handlers instead of a `null` data envelope
+pub fn no_content() -> axum::response::Response {
+    axum::http::StatusCode::NO_CONTENT.into_response()
+}
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+// Newline delimited JSON, one line per item and no envelope, for lists too large to buffer.
+// Items are serialized as they are pulled from `stream`, the status is already sent by then so
+// a serialization failure can only cut the body short.
+pub fn stream_json<S, T>(stream: S) -> axum::response::Response
+where
+    S: futures_util::Stream<Item = T> + Send + 'static,
+    T: serde::Serialize,
+{
+    use futures_util::StreamExt;
+
+    let lines = stream.map(|item| {
+        serde_json::to_vec(&item).map(|mut line| {
+            line.push(b'\n');
+            line
+        })
+    });
+    (
+        [(axum::http::header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        axum::body::Body::from_stream(lines),
+    )
+        .into_response()
+}
+
+pub fn with_headers<T: serde::Serialize>(
+    data: T,
+    headers: &[(&str, &str)],
+) -> Result<axum::response::Response, HeaderError> {
+    let mut response = success(data);
+    for (name, value) in headers {
+        insert_header(response.headers_mut(), name, value)?;
+    }
+    Ok(response)
+}
+
+pub fn insert_header(
+    headers: &mut axum::http::HeaderMap,
+    name: &str,
+    value: &str,
+) -> Result<(), HeaderError> {
+    let header_name = axum::http::HeaderName::from_bytes(name.as_bytes()).map_err(|source| {
+        HeaderError::InvalidName {
+            name: name.to_string(),
+            source,
+        }
+    })?;
+    let header_value =
+        axum::http::HeaderValue::from_str(value).map_err(|source| HeaderError::InvalidValue {
+            name: name.to_string(),
+            source,
+        })?;
+    headers.insert(header_name, header_value);
+    Ok(())
+}
+
+// For responses needing more than one of status, extra headers and meta, e.g.
+// `ResponseBuilder::new(data).status(StatusCode::CREATED).header("x-a", "1").meta(m).build()`
+#[derive(Debug)]
+pub struct ResponseBuilder<T, M = ()> {
+    data: T,
+    meta: Option<M>,
+    status: axum::http::StatusCode,
+    headers: Vec<(String, String)>,
+    warnings: Vec<String>,
+}
+
+impl<T: serde::Serialize> ResponseBuilder<T> {
+    pub fn new(data: T) -> Self {
+        ResponseBuilder {
+            data,
+            meta: None,
+            status: axum::http::StatusCode::OK,
+            headers: vec![],
+            warnings: vec![],
+        }
+    }
+}
+
+impl<T: serde::Serialize, M: serde::Serialize> ResponseBuilder<T, M> {
+    pub fn status(mut self, status: axum::http::StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    // headers are validated in `build`, an invalid one turns the response into a 500
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn warnings(mut self, warnings: Vec<String>) -> Self {
+        self.warnings.extend(warnings);
+        self
+    }
+
+    pub fn meta<N: serde::Serialize>(self, meta: N) -> ResponseBuilder<T, N> {
+        ResponseBuilder {
+            data: self.data,
+            meta: Some(meta),
+            status: self.status,
+            headers: self.headers,
+            warnings: self.warnings,
+        }
+    }
+
+    pub fn build(self) -> axum::response::Response {
+        if !self.status.is_success() {
+            return error::response(
+                &NotSuccessStatus(self.status),
+                "response.build",
+                error::trace_id().as_str(),
+            );
+        }
+        let mut response = format::render(
+            self.status,
+            &ApiSuccess {
+                success: true,
+                data: self.data,
+                meta: self.meta,
+                trace_id: current_trace_id(),
+                warnings: self.warnings,
+            },
+        );
+        for (name, value) in self.headers.iter() {
+            if let Err(err) = insert_header(response.headers_mut(), name, value) {
+                return error::response(&err, "response.build", error::trace_id().as_str());
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // what a conditional read handler does with the helpers
+    async fn conditional_get(headers: axum::http::HeaderMap) -> axum::response::Response {
+        let data = serde_json::json!({ "id": 1 });
+        let tag = etag(&data);
+        if etag_matches(&headers, tag.as_str()) {
+            not_modified(tag.as_str())
+        } else {
+            success_with_etag(data, tag.as_str())
+        }
+    }
+
+    fn conditional_router() -> axum::Router {
+        axum::Router::new().route("/item", axum::routing::get(conditional_get))
+    }
+
+    #[test]
+    fn etag_is_the_sha256_of_the_json() {
+        assert_eq!(
+            etag(&serde_json::json!({ "id": 1 })),
+            "\"037c9214eef74cc3887f3a4f085b4e17d76280dafd273b0ee160c09c4ba1cfd4\""
+        );
+    }
+
+    #[tokio::test]
+    async fn get_without_if_none_match_sends_body_and_etag() {
+        let request = axum::http::Request::get("/item")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send(conditional_router(), request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::ETAG],
+            etag(&serde_json::json!({ "id": 1 })).as_str()
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"]["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn get_with_matching_if_none_match_is_not_modified() {
+        let tag = etag(&serde_json::json!({ "id": 1 }));
+        let request = axum::http::Request::get("/item")
+            .header(axum::http::header::IF_NONE_MATCH, tag.as_str())
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send(conditional_router(), request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[axum::http::header::ETAG], tag.as_str());
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn with_headers_rejects_invalid_name() {
+        let err = with_headers("data", &[("x bad", "value")]).unwrap_err();
+        assert!(
+            matches!(err, HeaderError::InvalidName { ref name, .. } if name == "x bad"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn with_headers_rejects_invalid_value() {
+        let err = with_headers("data", &[("x-note", "line\nbreak")]).unwrap_err();
+        assert!(
+            matches!(err, HeaderError::InvalidValue { ref name, .. } if name == "x-note"),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn with_headers_sets_valid_headers() {
+        let response = with_headers("data", &[("x-note", "hello")]).unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(response.headers()["x-note"], "hello");
+    }
+
+    #[tokio::test]
+    async fn success_with_an_error_status_is_a_server_error() {
+        let response = success_with_status("data", axum::http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "INTERNAL_SERVER_ERROR");
+        assert!(body.get("data").is_none());
+    }
+
+    #[tokio::test]
+    async fn success_with_a_success_status_keeps_it() {
+        let response = success_with_status("data", axum::http::StatusCode::CREATED);
+        assert_eq!(response.status(), axum::http::StatusCode::CREATED);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"], "data");
+    }
+
+    fn page(page: u32, total: u64) -> crate::controller::types::Paginated<u32> {
+        let query = crate::controller::types::PageQuery { page, per_page: 10 };
+        crate::controller::types::Paginated::new(vec![], &query, total)
+    }
+
+    #[test]
+    fn middle_page_links_every_neighbour() {
+        let uri: axum::http::Uri = "/v1/items?filter=a&page=2&per_page=10".parse().unwrap();
+        let response = success_paginated(page(2, 30), &uri);
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::LINK],
+            "</v1/items?filter=a&page=1&per_page=10>; rel=\"first\", \
+             </v1/items?filter=a&page=1&per_page=10>; rel=\"prev\", \
+             </v1/items?filter=a&page=3&per_page=10>; rel=\"next\", \
+             </v1/items?filter=a&page=3&per_page=10>; rel=\"last\""
+        );
+    }
+
+    #[test]
+    fn first_and_last_pages_leave_out_prev_and_next() {
+        let uri: axum::http::Uri = "/v1/items".parse().unwrap();
+        assert_eq!(
+            page(1, 30).links(&uri),
+            "</v1/items?page=1&per_page=10>; rel=\"first\", \
+             </v1/items?page=2&per_page=10>; rel=\"next\", \
+             </v1/items?page=3&per_page=10>; rel=\"last\""
+        );
+        assert_eq!(
+            page(3, 30).links(&uri),
+            "</v1/items?page=1&per_page=10>; rel=\"first\", \
+             </v1/items?page=2&per_page=10>; rel=\"prev\", \
+             </v1/items?page=3&per_page=10>; rel=\"last\""
+        );
+        assert_eq!(
+            page(1, 0).links(&uri),
+            "</v1/items?page=1&per_page=10>; rel=\"first\""
+        );
+    }
+
+    #[tokio::test]
+    async fn no_content_has_no_body_and_no_content_type() {
+        let router =
+            axum::Router::new().route("/items/1", axum::routing::delete(|| async { no_content() }));
+        let request = axum::http::Request::delete("/items/1")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send_through(router, request).await;
+        assert_eq!(response.status(), axum::http::StatusCode::NO_CONTENT);
+        assert!(!response
+            .headers()
+            .contains_key(axum::http::header::CONTENT_TYPE));
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(bytes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn created_is_a_201_with_location() {
+        let response = created(serde_json::json!({ "id": 1 }), "/templates/1");
+        assert_eq!(response.status(), axum::http::StatusCode::CREATED);
+        assert_eq!(
+            response.headers()[axum::http::header::LOCATION],
+            "/templates/1"
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["id"], 1);
+    }
+
+    #[test]
+    fn created_with_an_invalid_location_is_a_server_error() {
+        let response = created(serde_json::json!({ "id": 1 }), "/templates/1\n");
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(!response
+            .headers()
+            .contains_key(axum::http::header::LOCATION));
+    }
+
+    #[tokio::test]
+    async fn builder_sets_status_headers_meta_and_data() {
+        let response = ResponseBuilder::new(serde_json::json!({ "id": 7 }))
+            .status(axum::http::StatusCode::CREATED)
+            .header("x-resource-version", "3")
+            .meta(serde_json::json!({ "source": "cache" }))
+            .build();
+        assert_eq!(response.status(), axum::http::StatusCode::CREATED);
+        assert_eq!(response.headers()["x-resource-version"], "3");
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["id"], 7);
+        assert_eq!(body["meta"]["source"], "cache");
+    }
+
+    #[test]
+    fn builder_with_an_error_status_or_bad_header_is_a_server_error() {
+        let response = ResponseBuilder::new("data")
+            .status(axum::http::StatusCode::BAD_REQUEST)
+            .build();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        let response = ResponseBuilder::new("data").header("bad name", "1").build();
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn accepted_is_a_202_with_the_data() {
+        let response = accepted(serde_json::json!({ "job": "j-1" }));
+        assert_eq!(response.status(), axum::http::StatusCode::ACCEPTED);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["success"], true);
+        assert_eq!(body["data"]["job"], "j-1");
+    }
+
+    #[test]
+    fn accepted_at_adds_the_status_location() {
+        let response = accepted_at(serde_json::json!({ "job": "j-1" }), "/jobs/j-1");
+        assert_eq!(response.status(), axum::http::StatusCode::ACCEPTED);
+        assert_eq!(
+            response.headers()[axum::http::header::LOCATION],
+            "/jobs/j-1"
+        );
+    }
+
+    #[tokio::test]
+    async fn success_trace_id_is_the_request_id() {
+        let router = axum::Router::new().route(
+            "/item",
+            axum::routing::get(|| async { success(serde_json::json!({ "id": 1 })) }),
+        );
+        let request = axum::http::Request::get("/item")
+            .header("x-request-id", "req-55")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = crate::router::tests::send_through(router, request).await;
+        assert_eq!(response.headers()["x-request-id"], "req-55");
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["trace_id"], "req-55");
+    }
+
+    #[tokio::test]
+    async fn success_outside_of_a_request_has_no_trace_id() {
+        let body = crate::router::tests::body_json(success("data")).await;
+        assert!(body.get("trace_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn warnings_are_sent_when_present() {
+        let response = success_with_warnings(
+            "data",
+            vec!["`name` is deprecated, use `title`".to_string()],
+        );
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(
+            body["warnings"],
+            serde_json::json!(["`name` is deprecated, use `title`"])
+        );
+        assert_eq!(body["data"], "data");
+    }
+
+    #[tokio::test]
+    async fn warnings_are_omitted_when_empty() {
+        let body = crate::router::tests::body_json(success_with_warnings("data", vec![])).await;
+        assert!(body.get("warnings").is_none());
+    }
+
+    #[tokio::test]
+    async fn stream_json_sends_one_object_per_line() {
+        let items = (1..=3).map(|id| serde_json::json!({ "id": id }));
+        let response = stream_json(futures_util::stream::iter(items));
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            NDJSON_CONTENT_TYPE
+        );
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(
+            std::str::from_utf8(&bytes).unwrap(),
+            "{\"id\":1}\n{\"id\":2}\n{\"id\":3}\n"
+        );
+    }
+
+    // what an update handler does with `check_if_match`
+    async fn conditional_put(
+        headers: axum::http::HeaderMap,
+    ) -> Result<axum::response::Response, error::AppError> {
+        let current = serde_json::json!({ "id": 1 });
+        check_if_match(&headers, etag(&current).as_str())
+            .map_err(|err| error::AppError::new(err, "item.update"))?;
+        Ok(success(serde_json::json!({ "id": 1, "updated": true })))
+    }
+
+    async fn put_with_if_match(if_match: &str) -> axum::response::Response {
+        let router = axum::Router::new().route("/item", axum::routing::put(conditional_put));
+        let request = axum::http::Request::put("/item")
+            .header(axum::http::header::IF_MATCH, if_match)
+            .body(axum::body::Body::empty())
+            .unwrap();
+        crate::router::tests::send(router, request).await
+    }
+
+    #[tokio::test]
+    async fn if_match_with_a_stale_etag_is_a_precondition_failure() {
+        let response = put_with_if_match("\"stale\"").await;
+        assert_eq!(
+            response.status(),
+            axum::http::StatusCode::PRECONDITION_FAILED
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["error"]["code"], "PRECONDITION_FAILED");
+        assert_eq!(body["error"]["operation"], "item.update");
+    }
+
+    #[tokio::test]
+    async fn if_match_with_the_current_etag_passes() {
+        let tag = etag(&serde_json::json!({ "id": 1 }));
+        for if_match in [tag.as_str(), "\"stale\", *"] {
+            let response = put_with_if_match(if_match).await;
+            assert_eq!(
+                response.status(),
+                axum::http::StatusCode::OK,
+                "{}",
+                if_match
+            );
+            let body = crate::router::tests::body_json(response).await;
+            assert_eq!(body["data"]["updated"], true);
+        }
+    }
+
+    #[tokio::test]
+    async fn cacheable_success_sets_cache_control_in_whole_seconds() {
+        let response = success_cacheable("data", std::time::Duration::from_millis(60_900));
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[axum::http::header::CACHE_CONTROL],
+            "public, max-age=60"
+        );
+        let body = crate::router::tests::body_json(response).await;
+        assert_eq!(body["data"], "data");
+    }
+}
@@ -1,10 +1,249 @@
 pub async fn health_router() -> axum::Router {
+    axum::Router::new()
+        .route(
+            "/v1/api/health/",
+            axum::routing::get(crate::controller::health::health),
+        )
+        .route(
+            "/v1/api/ready/",
+            axum::routing::get(crate::controller::health::ready),
+        )
+}
+
+pub async fn error_codes_router() -> axum::Router {
+    axum::Router::new().route(
+        "/v1/api/error-codes/",
+        axum::routing::get(crate::controller::error_codes::list),
+    )
+}
+
+pub async fn metrics_router() -> axum::Router {
     axum::Router::new().route(
-        "/v1/api/health/",
-        axum::routing::get(crate::controller::health),
+        "/metrics",
+        axum::routing::get(crate::controller::metrics::metrics),
     )
 }
 
-pub async fn routes() -> axum::Router {
-    axum::Router::new().merge(health_router().await)
+pub async fn docs_router() -> axum::Router {
+    axum::Router::new()
+        .route(
+            "/openapi.json",
+            axum::routing::get(crate::controller::openapi::openapi),
+        )
+        .route(
+            "/docs",
+            axum::routing::get(crate::controller::openapi::docs),
+        )
+}
+
+pub async fn routes(settings: &crate::settings::Settings) -> axum::Router {
+    let router = axum::Router::new()
+        .merge(health_router().await)
+        .merge(error_codes_router().await)
+        .merge(metrics_router().await)
+        .merge(docs_router().await)
+        .fallback(crate::controller::fallback::not_found)
+        // only covers the routes merged above, keep it after the last `merge`
+        .method_not_allowed_fallback(crate::controller::fallback::method_not_allowed);
+    with_middleware(router, settings)
+}
+
+// The middleware stack every route is served through, outermost last
+pub fn with_middleware(router: axum::Router, settings: &crate::settings::Settings) -> axum::Router {
+    let router = router
+        .layer(axum::middleware::from_fn_with_state(
+            crate::middleware::timeout::Timeouts::new(&settings.timeout),
+            crate::middleware::timeout::timeout,
+        ))
+        .layer(axum::extract::DefaultBodyLimit::max(
+            settings.service.max_body_size,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            settings.service.max_body_size,
+            crate::middleware::body_limit::body_limit,
+        ))
+        .layer(crate::middleware::compression::layer(
+            settings.response.compression_min_size,
+        ));
+
+    let router = if settings.rate_limit.enabled {
+        router.layer(axum::middleware::from_fn_with_state(
+            crate::middleware::rate_limit::RateLimiter::new(&settings.rate_limit),
+            crate::middleware::rate_limit::rate_limit,
+        ))
+    } else {
+        router
+    };
+
+    let router = router.layer(axum::middleware::from_fn(
+        crate::middleware::negotiate::negotiate,
+    ));
+
+    let router = if settings.response.response_time_header {
+        router.layer(axum::middleware::from_fn(
+            crate::middleware::response_time::response_time,
+        ))
+    } else {
+        router
+    };
+
+    router
+        .layer(axum::middleware::from_fn(crate::middleware::trace::trace))
+        .layer(axum::middleware::from_fn(
+            crate::middleware::request_id::request_id,
+        ))
+        .layer(crate::middleware::cors::layer(&settings.cors))
+}
+
+// Helpers for the tests sending requests through the full middleware stack of `routes`
+#[cfg(test)]
+pub(crate) mod tests {
+    // `settings/default.toml` without reading files, the tests run from the crate directory
+    pub fn settings() -> crate::settings::Settings {
+        crate::settings::Settings {
+            service: crate::settings::ServiceSettings {
+                bind: "127.0.0.1".to_string(),
+                port: 0,
+                profile: "test".to_string(),
+                max_body_size: crate::middleware::body_limit::DEFAULT_MAX_BODY_SIZE,
+                drain_timeout_secs: 1,
+            },
+            response: Default::default(),
+            cors: Default::default(),
+            auth: Default::default(),
+            rate_limit: Default::default(),
+            request_id: Default::default(),
+            timeout: Default::default(),
+            pagination: Default::default(),
+        }
+    }
+
+    pub async fn send(
+        router: axum::Router,
+        request: axum::http::Request<axum::body::Body>,
+    ) -> axum::response::Response {
+        use tower::ServiceExt;
+
+        router.oneshot(request).await.expect("router is infallible")
+    }
+
+    // `request` through `routes` with the default settings
+    pub async fn send_default(
+        request: axum::http::Request<axum::body::Body>,
+    ) -> axum::response::Response {
+        send(super::routes(&settings()).await, request).await
+    }
+
+    pub async fn body_json(response: axum::response::Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("readable body");
+        serde_json::from_slice(&bytes).expect("json body")
+    }
+
+    // `router` behind the same middleware as `routes`, for routes only the tests have
+    pub async fn send_through(
+        router: axum::Router,
+        request: axum::http::Request<axum::body::Body>,
+    ) -> axum::response::Response {
+        send(super::with_middleware(router, &settings()), request).await
+    }
+
+    // checks the parts every error body has and returns it for the specific ones
+    async fn error_envelope(
+        response: axum::response::Response,
+        status: axum::http::StatusCode,
+        code: &str,
+    ) -> serde_json::Value {
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            response.headers()[axum::http::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let request_id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = body_json(response).await;
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], code);
+        assert!(body["error"]["code_id"].is_u64());
+        assert!(!body["error"]["message"].as_str().unwrap().is_empty());
+        assert_eq!(body["error"]["status"], status.as_u16());
+        assert_eq!(body["error"]["trace_id"], request_id.as_str());
+        // `details` is only sent at full verbosity
+        assert!(body["error"].get("details").is_none());
+        body
+    }
+
+    #[derive(serde::Deserialize)]
+    struct Item {
+        name: String,
+    }
+
+    fn probe_router() -> axum::Router {
+        async fn create(
+            crate::controller::extract::Json(item): crate::controller::extract::Json<Item>,
+        ) -> axum::response::Response {
+            crate::response::success(item.name)
+        }
+
+        async fn list(
+            crate::controller::extract::Query(query): crate::controller::extract::Query<
+                crate::controller::types::PageQuery,
+            >,
+        ) -> axum::response::Response {
+            crate::response::success(query.page)
+        }
+
+        axum::Router::new().route("/items", axum::routing::get(list).post(create))
+    }
+
+    #[tokio::test]
+    async fn unknown_route_gets_the_not_found_envelope() {
+        let request = axum::http::Request::get("/v1/api/nothing-here/")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = send_default(request).await;
+        error_envelope(response, axum::http::StatusCode::NOT_FOUND, "NOT_FOUND").await;
+    }
+
+    #[tokio::test]
+    async fn wrong_method_gets_the_method_not_allowed_envelope() {
+        let request = axum::http::Request::delete("/v1/api/health/")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = send_default(request).await;
+        error_envelope(
+            response,
+            axum::http::StatusCode::METHOD_NOT_ALLOWED,
+            "METHOD_NOT_ALLOWED",
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn malformed_json_gets_the_bad_request_envelope() {
+        let request = axum::http::Request::post("/items")
+            .header(axum::http::header::CONTENT_TYPE, "application/json")
+            .body(axum::body::Body::from(r#"{"name": 1}"#))
+            .unwrap();
+        let response = send_through(probe_router(), request).await;
+        let body =
+            error_envelope(response, axum::http::StatusCode::BAD_REQUEST, "BAD_REQUEST").await;
+        assert_eq!(body["error"]["operation"], "request.json");
+        assert!(body["error"]["description"].is_string());
+    }
+
+    #[tokio::test]
+    async fn invalid_query_gets_the_bad_request_envelope() {
+        let request = axum::http::Request::get("/items?page=first")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = send_through(probe_router(), request).await;
+        let body =
+            error_envelope(response, axum::http::StatusCode::BAD_REQUEST, "BAD_REQUEST").await;
+        assert_eq!(body["error"]["operation"], "request.query");
+        assert!(body["error"]["description"].is_string());
+    }
 }
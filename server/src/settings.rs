@@ -1,6 +1,20 @@
 #[derive(serde::Deserialize, Debug)]
 pub struct Settings {
     pub service: ServiceSettings,
+    #[serde(default)]
+    pub response: ResponseSettings,
+    #[serde(default)]
+    pub cors: CorsSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(rename = "rate-limit", default)]
+    pub rate_limit: RateLimitSettings,
+    #[serde(rename = "request-id", default)]
+    pub request_id: RequestIdSettings,
+    #[serde(default)]
+    pub timeout: TimeoutSettings,
+    #[serde(default)]
+    pub pagination: PaginationSettings,
 }
 
 #[derive(serde::Deserialize, Debug)]
@@ -8,6 +22,190 @@ pub struct ServiceSettings {
     pub bind: String,
     pub port: u16,
     pub profile: String,
+    // largest accepted request body in bytes
+    #[serde(rename = "max-body-size", default = "default_max_body_size")]
+    pub max_body_size: usize,
+    // seconds in-flight requests get to finish after a shutdown signal
+    #[serde(rename = "drain-timeout-secs", default = "default_drain_timeout_secs")]
+    pub drain_timeout_secs: u64,
+}
+
+fn default_drain_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_body_size() -> usize {
+    crate::middleware::body_limit::DEFAULT_MAX_BODY_SIZE
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ResponseSettings {
+    // how much of an error the client gets to see: public, description or full, never use
+    // full in production
+    #[serde(default)]
+    pub verbosity: crate::response::error::Verbosity,
+    // bodies smaller than this many bytes are not compressed
+    #[serde(
+        rename = "compression-min-size",
+        default = "default_compression_min_size"
+    )]
+    pub compression_min_size: u16,
+    // `details` as one string or `details_chain` as an array, only sent at full verbosity
+    #[serde(rename = "details-format", default)]
+    pub details_format: crate::response::error::DetailsFormat,
+    #[serde(rename = "timestamp-format", default)]
+    pub timestamp_format: crate::response::error::TimestampFormat,
+    // add the `X-Response-Time-Ms` header to every response
+    #[serde(rename = "response-time-header", default)]
+    pub response_time_header: bool,
+    // replace the message of 5xx errors with the generic one of their code
+    #[serde(rename = "mask-server-errors", default = "default_true")]
+    pub mask_server_errors: bool,
+    // when set every error links to it as `support_url`
+    #[serde(rename = "support-url", default)]
+    pub support_url: Option<String>,
+    // at most this many seconds are added to `Retry-After` at random, 0 disables it
+    #[serde(rename = "retry-after-jitter-secs", default)]
+    pub retry_after_jitter_secs: u64,
+    // fixes the jitter sequence, for reproducing a run. Seeded from the clock when unset.
+    #[serde(rename = "retry-after-jitter-seed", default)]
+    pub retry_after_jitter_seed: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_compression_min_size() -> u16 {
+    crate::middleware::compression::DEFAULT_MIN_SIZE
+}
+
+impl Default for ResponseSettings {
+    fn default() -> Self {
+        ResponseSettings {
+            verbosity: Default::default(),
+            compression_min_size: default_compression_min_size(),
+            details_format: Default::default(),
+            timestamp_format: Default::default(),
+            response_time_header: false,
+            mask_server_errors: true,
+            support_url: None,
+            retry_after_jitter_secs: 0,
+            retry_after_jitter_seed: None,
+        }
+    }
+}
+
+// `*` allows everything, an empty `allowed-origins` disables cross origin requests
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct CorsSettings {
+    #[serde(rename = "allowed-origins", default)]
+    pub allowed_origins: Vec<String>,
+    #[serde(rename = "allowed-methods", default)]
+    pub allowed_methods: Vec<String>,
+    #[serde(rename = "allowed-headers", default)]
+    pub allowed_headers: Vec<String>,
+}
+
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct AuthSettings {
+    // HS256 secret the bearer tokens are signed with, tokens are rejected while it is empty
+    #[serde(rename = "jwt-secret", default)]
+    pub jwt_secret: String,
+}
+
+// Incoming header carrying the request id, e.g. `x-correlation-id` or `traceparent` with the
+// `traceparent` format
+#[derive(serde::Deserialize, Debug)]
+pub struct RequestIdSettings {
+    #[serde(default = "default_request_id_header")]
+    pub header: String,
+    #[serde(default)]
+    pub format: crate::middleware::request_id::IdFormat,
+}
+
+fn default_request_id_header() -> String {
+    crate::middleware::request_id::REQUEST_ID_HEADER.to_string()
+}
+
+impl Default for RequestIdSettings {
+    fn default() -> Self {
+        RequestIdSettings {
+            header: default_request_id_header(),
+            format: Default::default(),
+        }
+    }
+}
+
+// Longest a request may take before it is answered with GatewayTimeout, `routes` overrides
+// the default per route pattern, e.g. `"/v1/api/ready/" = 5`
+#[derive(serde::Deserialize, Debug)]
+pub struct TimeoutSettings {
+    #[serde(rename = "default-secs", default = "default_timeout_secs")]
+    pub default_secs: u64,
+    #[serde(default)]
+    pub routes: std::collections::HashMap<String, u64>,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        TimeoutSettings {
+            default_secs: default_timeout_secs(),
+            routes: Default::default(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct PaginationSettings {
+    // largest `per_page` the list endpoints accept
+    #[serde(rename = "max-per-page", default = "default_max_per_page")]
+    pub max_per_page: u32,
+}
+
+fn default_max_per_page() -> u32 {
+    crate::controller::types::DEFAULT_MAX_PER_PAGE
+}
+
+impl Default for PaginationSettings {
+    fn default() -> Self {
+        PaginationSettings {
+            max_per_page: default_max_per_page(),
+        }
+    }
+}
+
+// every client may send `requests` requests per `window-secs`, bursts included
+#[derive(serde::Deserialize, Debug)]
+pub struct RateLimitSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_rate_limit_requests")]
+    pub requests: u32,
+    #[serde(rename = "window-secs", default = "default_rate_limit_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_rate_limit_requests() -> u32 {
+    100
+}
+
+fn default_rate_limit_window_secs() -> u64 {
+    60
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        RateLimitSettings {
+            enabled: false,
+            requests: default_rate_limit_requests(),
+            window_secs: default_rate_limit_window_secs(),
+        }
+    }
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -22,13 +220,128 @@ pub struct TelemetrySettings {
     pub log_level: String,
 }
 
+// Environment variables overriding single settings of the toml files, for deployments which
+// configure through the environment. List settings take a comma separated value.
+pub const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("APP_BIND", "service.bind"),
+    ("APP_PORT", "service.port"),
+    ("APP_MAX_BODY_SIZE", "service.max-body-size"),
+    ("APP_DRAIN_TIMEOUT_SECS", "service.drain-timeout-secs"),
+    ("APP_VERBOSITY", "response.verbosity"),
+    ("APP_REQUEST_ID_HEADER", "request-id.header"),
+    ("APP_REQUEST_ID_FORMAT", "request-id.format"),
+    ("APP_CORS_ALLOWED_ORIGINS", "cors.allowed-origins"),
+    ("APP_JWT_SECRET", "auth.jwt-secret"),
+    ("APP_MAX_PER_PAGE", "pagination.max-per-page"),
+];
+
+const LIST_SETTINGS: &[&str] = &["cors.allowed-origins"];
+
 impl Settings {
+    // `settings/default.toml`, then `settings/<profile>.toml`, then the `ENV_OVERRIDES`
     pub fn with_file(profile_name: &str) -> Result<Self, config::ConfigError> {
+        Settings::with_file_and_env(profile_name, std::env::vars())
+    }
+
+    pub fn with_file_and_env<I: IntoIterator<Item = (String, String)>>(
+        profile_name: &str,
+        env: I,
+    ) -> Result<Self, config::ConfigError> {
         let settings_file_path = format!("settings/{}.toml", profile_name);
-        let settings = config::Config::builder()
+        let builder = config::Config::builder()
             .add_source(config::File::with_name("settings/default.toml"))
-            .add_source(config::File::with_name(&settings_file_path))
-            .build()?;
-        settings.try_deserialize()
+            .add_source(config::File::with_name(&settings_file_path));
+        Settings::with_env(builder, env)
+    }
+
+    // applies the `ENV_OVERRIDES` found in `env` on top of the sources already in `builder`
+    fn with_env<I: IntoIterator<Item = (String, String)>>(
+        mut builder: config::ConfigBuilder<config::builder::DefaultState>,
+        env: I,
+    ) -> Result<Self, config::ConfigError> {
+        for (name, value) in env {
+            let key = match ENV_OVERRIDES.iter().find(|(var, _)| *var == name) {
+                Some((_, key)) => *key,
+                None => continue,
+            };
+            builder = if LIST_SETTINGS.contains(&key) {
+                let values: Vec<String> = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|value| !value.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                builder.set_override(key, values)?
+            } else {
+                builder.set_override(key, value)?
+            };
+        }
+        builder.build()?.try_deserialize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn load(env: &[(&str, &str)]) -> Result<super::Settings, config::ConfigError> {
+        let builder = config::Config::builder()
+            .add_source(config::File::from_str(
+                include_str!("../../settings/default.toml"),
+                config::FileFormat::Toml,
+            ))
+            .add_source(config::File::from_str(
+                include_str!("../../settings/dev.toml"),
+                config::FileFormat::Toml,
+            ));
+        let env = env
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()));
+        super::Settings::with_env(builder, env)
+    }
+
+    fn from_env(env: &[(&str, &str)]) -> super::Settings {
+        load(env).unwrap()
+    }
+
+    #[test]
+    fn files_apply_without_env() {
+        let settings = from_env(&[]);
+        assert_eq!(settings.service.port, 8000);
+        assert_eq!(settings.service.profile, "dev");
+        assert_eq!(settings.cors.allowed_origins, vec!["*"]);
+    }
+
+    #[test]
+    fn env_overrides_the_files() {
+        let settings = from_env(&[
+            ("APP_PORT", "9000"),
+            ("APP_JWT_SECRET", "from-env"),
+            ("APP_MAX_PER_PAGE", "50"),
+        ]);
+        assert_eq!(settings.service.port, 9000);
+        assert_eq!(settings.auth.jwt_secret, "from-env");
+        assert_eq!(settings.pagination.max_per_page, 50);
+    }
+
+    #[test]
+    fn list_settings_are_comma_separated() {
+        let settings = from_env(&[(
+            "APP_CORS_ALLOWED_ORIGINS",
+            "https://a.example, https://b.example,",
+        )]);
+        assert_eq!(
+            settings.cors.allowed_origins,
+            vec!["https://a.example", "https://b.example"]
+        );
+    }
+
+    #[test]
+    fn unknown_variables_are_ignored() {
+        let settings = from_env(&[("APP_UNKNOWN", "1"), ("PORT", "1")]);
+        assert_eq!(settings.service.port, 8000);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(load(&[("APP_PORT", "not-a-port")]).is_err());
     }
 }